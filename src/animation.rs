@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::format::Format;
+use crate::video::{self, Clip};
+
+// Browsers play frames without a delay, or a really short one, at 10 fps
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Playback of the animation of the current image
+pub struct Playback {
+    image: Option<PathBuf>,
+    delays: Vec<Duration>,
+    /// Metadata of the current image, when it's a video clip. Only a frame
+    /// of the clip is displayed.
+    pub clip: Option<Clip>,
+    pub frame: usize,
+    pub paused: bool,
+    frame_shown: Instant,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            image: None,
+            delays: vec![],
+            clip: None,
+            frame: 0,
+            paused: false,
+            frame_shown: Instant::now(),
+        }
+    }
+}
+
+impl Playback {
+    /// Start playing the image from the beginning, unless it's already the
+    /// one being played
+    pub fn load(&mut self, image: Option<PathBuf>) {
+        if self.image == image {
+            return;
+        }
+        self.delays = image.as_deref().map(frame_delays).unwrap_or_default();
+        self.clip = image
+            .as_deref()
+            .filter(|image| Format::from_path(image) == Some(Format::Video))
+            .map(video::probe);
+        self.image = image;
+        self.frame = 0;
+        self.frame_shown = Instant::now();
+    }
+
+    /// Number of frames, zero for still images
+    pub fn frame_count(&self) -> usize {
+        self.delays.len()
+    }
+
+    /// When the next frame is due, if the image is being played at all
+    pub fn next_frame_at(&self) -> Option<Instant> {
+        if self.paused || self.delays.len() < 2 {
+            return None;
+        }
+        Some(self.frame_shown + self.delays[self.frame])
+    }
+
+    /// Move on to the frame due by now
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        while let Some(next) = self.next_frame_at() {
+            if next > now {
+                break;
+            }
+            self.frame = (self.frame + 1) % self.delays.len();
+            // Don't try to catch up after falling way behind
+            self.frame_shown = if now - next > DEFAULT_DELAY {
+                now
+            } else {
+                next
+            };
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.frame_shown = Instant::now();
+    }
+
+    /// Pause and move `delta` frames back or forth
+    pub fn step(&mut self, delta: isize) {
+        if self.delays.is_empty() {
+            return;
+        }
+        let count = self.delays.len() as isize;
+        self.frame = (self.frame as isize + delta).rem_euclid(count) as usize;
+        self.paused = true;
+    }
+}
+
+/// Delays of the frames of an animated GIF or APNG, empty for anything else.
+/// Only the metadata is read, frames are decoded by the renderers.
+pub fn frame_delays(path: &Path) -> Vec<Duration> {
+    let delays = match Format::from_path(path) {
+        Some(Format::Gif) => gif_delays(path),
+        Some(Format::Png) => apng_delays(path),
+        _ => None,
+    };
+    delays.unwrap_or_default()
+}
+
+fn gif_delays(path: &Path) -> Option<Vec<Duration>> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options
+        .read_info(BufReader::new(File::open(path).ok()?))
+        .ok()?;
+
+    let mut delays = vec![];
+    while let Some(frame) = decoder.next_frame_info().ok()? {
+        // In hundredths of a second
+        delays.push(frame_delay(frame.delay as u32 * 10, 1000));
+    }
+    Some(delays)
+}
+
+fn apng_delays(path: &Path) -> Option<Vec<Duration>> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(path).ok()?));
+    let mut reader = decoder.read_info().ok()?;
+    let frames = reader.info().animation_control()?.num_frames as usize;
+
+    let mut delays = vec![];
+    // The default image may or may not be the first frame
+    if let Some(control) = reader.info().frame_control() {
+        delays.push(frame_delay(
+            control.delay_num as u32,
+            control.delay_den as u32,
+        ));
+    }
+    while delays.len() < frames {
+        let control = reader.next_frame_info().ok()?;
+        delays.push(frame_delay(
+            control.delay_num as u32,
+            control.delay_den as u32,
+        ));
+    }
+    Some(delays)
+}
+
+// A delay of numerator/denominator seconds
+fn frame_delay(numerator: u32, denominator: u32) -> Duration {
+    // As per the APNG spec, a denominator of 0 means hundredths of a second
+    let denominator = if denominator == 0 { 100 } else { denominator };
+    let delay = Duration::from_millis(numerator as u64 * 1000 / denominator as u64);
+    if delay < MIN_DELAY {
+        DEFAULT_DELAY
+    } else {
+        delay
+    }
+}
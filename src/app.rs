@@ -1,12 +1,27 @@
 use anyhow::{anyhow, Result};
-use std::io::prelude::*;
+use crossbeam_channel::Sender;
+use std::io::{self, prelude::*};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
     fs::File,
-    path::{Path, PathBuf},
+    os::unix::ffi::OsStringExt,
+    path::{self, Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
     time::Instant,
 };
 
+use crate::animation::Playback;
+use crate::discovery::{Discovery, Images};
+use crate::duplicates::{Destinations, Duplicates};
+use crate::event::Event;
+use crate::filter::Filter;
+use crate::format::Format;
+use crate::order::Order;
+use crate::similar::Similar;
+use crate::view::View;
+use crate::watch;
 use crate::Opt;
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -21,6 +36,10 @@ const TABS: [TabId; 2] = [TabId::Main, TabId::Script];
 pub enum Action {
     Skip(PathBuf),
     Move(PathBuf, PathBuf),
+    Copy(PathBuf, PathBuf),
+    /// A symbolic link to the image, relative to where it is or absolute
+    Link(PathBuf, PathBuf, bool),
+    HardLink(PathBuf, PathBuf),
     Rename(String),
     MkDir(PathBuf),
     Delete(PathBuf),
@@ -34,7 +53,57 @@ impl Action {
     pub fn queue_step(&self) -> usize {
         match self {
             Action::Skip(_) | Action::Move(_, _) | Action::Delete(_) => 1,
-            Action::Rename(_) | Action::MkDir(_) => 0,
+            // Copied or linked images stay put, to be filed somewhere else too
+            Action::Copy(_, _)
+            | Action::Link(_, _, _)
+            | Action::HardLink(_, _)
+            | Action::Rename(_)
+            | Action::MkDir(_) => 0,
+        }
+    }
+}
+
+/// What a bound key does with images
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Mode {
+    #[default]
+    Move,
+    Copy,
+    Symlink,
+    RelativeSymlink,
+    HardLink,
+}
+
+impl Mode {
+    pub const VARIANTS: &'static [&'static str] =
+        &["move", "copy", "symlink", "relative-symlink", "hardlink"];
+
+    pub fn name(self) -> &'static str {
+        Mode::VARIANTS[self as usize]
+    }
+
+    pub fn action(self, image: PathBuf, target: PathBuf) -> Action {
+        match self {
+            Mode::Move => Action::Move(image, target),
+            Mode::Copy => Action::Copy(image, target),
+            Mode::Symlink => Action::Link(image, target, false),
+            Mode::RelativeSymlink => Action::Link(image, target, true),
+            Mode::HardLink => Action::HardLink(image, target),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "move" => Ok(Mode::Move),
+            "copy" => Ok(Mode::Copy),
+            "symlink" => Ok(Mode::Symlink),
+            "relative-symlink" => Ok(Mode::RelativeSymlink),
+            "hardlink" => Ok(Mode::HardLink),
+            _ => Err(anyhow!("unknown mode `{}`", s)),
         }
     }
 }
@@ -43,14 +112,31 @@ pub struct App {
     pub tab: usize,
     pub script_offset: (u16, u16),
     pub images: Vec<PathBuf>,
+    pub discovery: Discovery,
+    pub order: Order,
+    pub group_sidecars: bool,
+    pub duplicates: Duplicates,
+    pub similar: Similar,
+    // Images with an identical copy in a bound folder, and where it is
+    pub already_sorted: HashMap<PathBuf, PathBuf>,
+    // RAW files sorted along with the JPEG they were shot with
+    pub sidecars: HashMap<PathBuf, Vec<PathBuf>>,
+    // Images found so far that RAW files can pair up with, and RAW files
+    // found without one, by their path without extension
+    primaries: HashMap<PathBuf, PathBuf>,
+    lone_raws: HashMap<PathBuf, PathBuf>,
     pub current: usize,
     pub key_mapping: BTreeMap<char, PathBuf>,
+    // Keys bound to something else than moving images
+    pub key_modes: HashMap<char, Mode>,
     pub actions: Vec<Action>,
     pub output: String,
     pub enable_input: bool,
     pub input: Vec<char>,
     pub input_idx: usize,
     pub last_save: Option<Instant>,
+    pub view: View,
+    pub playback: Playback,
 }
 
 impl Default for App {
@@ -60,28 +146,218 @@ impl Default for App {
             script_offset: (0, 0),
             current: 0,
             images: vec![],
+            discovery: Discovery::default(),
+            order: Order::default(),
+            group_sidecars: false,
+            duplicates: Duplicates::default(),
+            similar: Similar::default(),
+            already_sorted: HashMap::new(),
+            sidecars: HashMap::new(),
+            primaries: HashMap::new(),
+            lone_raws: HashMap::new(),
             key_mapping: BTreeMap::new(),
+            key_modes: HashMap::new(),
             actions: vec![],
             output: "".to_string(),
             enable_input: false,
             input: vec![],
             input_idx: 0,
             last_save: None,
+            view: View::default(),
+            playback: Playback::default(),
         }
     }
 }
 
 impl App {
-    pub fn new(opt: Opt) -> Result<Self> {
-        let images = App::parse_images(opt.input, opt.recurse);
-        let (key_mapping, actions) = App::parse_key_mapping(opt.bind)?;
+    pub fn new(opt: Opt, events: Sender<Event>) -> Result<Self> {
+        let (key_mapping, key_modes, actions) = App::parse_key_mapping(opt.bind)?;
+        let inputs = App::read_inputs(opt.input, opt.files_from, opt.null)?;
+        let order = Order::new(opt.sort, opt.reverse, opt.seed);
+
+        let filter = Arc::new(Filter::new(opt.include, opt.exclude, opt.ignore_file)?);
+        let destinations = Destinations {
+            existing: opt.existing,
+            folders: key_mapping.values().cloned().collect(),
+        };
 
-        Ok(App {
-            images,
+        let mut app = App {
+            discovery: Discovery {
+                inputs,
+                recurse: opt.recurse,
+                filter,
+                formats: opt.formats,
+                limit: opt.limit,
+                symlinks: opt.follow_symlinks,
+                order: order.clone(),
+                destinations,
+                events: Some(events),
+                ..Discovery::default()
+            },
+            order,
+            group_sidecars: opt.group_sidecars,
+            duplicates: Duplicates::new(opt.duplicates),
+            similar: Similar::new(opt.similar),
             key_mapping,
+            key_modes,
             actions,
             output: opt.output,
             ..App::default()
+        };
+        if opt.watch {
+            let folders = app
+                .discovery
+                .inputs
+                .iter()
+                .filter(|input| input.is_dir())
+                .cloned()
+                .collect();
+            watch::watch(folders, &app.discovery)?;
+        }
+        app.next_batch();
+        Ok(app)
+    }
+
+    /// Discover the images following the ones already being sorted, up to
+    /// the limit, instead of having to run the script and start over
+    pub fn next_batch(&mut self) {
+        self.discovery.start();
+    }
+
+    /// Queue images as they are discovered, the first ones can be sorted
+    /// while the rest are being looked for. Each one goes in its place among
+    /// the images not shown yet.
+    pub fn add_images(&mut self, images: Images) {
+        self.already_sorted.extend(images.existing);
+        let mut images = images.paths;
+
+        if self.duplicates.enabled {
+            self.duplicates.queue(images);
+            // Images from the watcher are compared right away
+            if !self.discovery.running {
+                if let Some(events) = self.discovery.events.clone() {
+                    self.duplicates.start(&self.order, events);
+                }
+            }
+            return;
+        }
+        if self.group_sidecars {
+            images = self.pair_sidecars(images);
+        }
+        for image in images {
+            let start = self.reorder_start();
+            self.order.insert(&mut self.images, start, image);
+        }
+    }
+
+    /// Start looking for similar images, or duplicates, once they have all
+    /// been found
+    pub fn finish_batch(&mut self, truncated: bool) {
+        self.discovery.running = false;
+        self.discovery.truncated = truncated;
+
+        // Only duplicates are sorted, once they are all known
+        if self.duplicates.enabled {
+            if let Some(events) = self.discovery.events.clone() {
+                self.duplicates.start(&self.order, events);
+            }
+            return;
+        }
+
+        // The image on screen is compared too, to bring the ones looking
+        // like it next
+        if let Some(events) = self.discovery.events.clone() {
+            let left = self.images[self.current.min(self.images.len())..].to_vec();
+            self.similar.start(left, events);
+        }
+    }
+
+    /// Put images that look alike next to each other, the clusters keep
+    /// the order of their first image. Clusters may take minutes, so only
+    /// the images not shown yet are moved, and the ones looking like the
+    /// image on screen come right after it.
+    pub fn add_clusters(&mut self, clusters: Vec<Vec<PathBuf>>) {
+        let mut images = self.images.split_off(self.reorder_start());
+        let mut position: HashMap<PathBuf, usize> = HashMap::new();
+        for (i, image) in clusters.iter().flatten().enumerate() {
+            position.insert(image.clone(), i);
+        }
+        let shown = self
+            .current_image()
+            .and_then(|image| clusters.iter().find(|cluster| cluster.contains(&image)));
+        // Images added since are left at the end
+        images.sort_by_cached_key(|image| {
+            let alike = shown.is_some_and(|cluster| cluster.contains(image));
+            (!alike, position.get(image).copied().unwrap_or(usize::MAX))
+        });
+        self.images.extend(images);
+        self.similar.add_clusters(clusters);
+    }
+
+    // Where images left to sort can be moved around from. The current one
+    // stays put if the user is busy renaming, copying or linking it.
+    fn reorder_start(&self) -> usize {
+        let started = self.enable_input
+            || matches!(
+                self.actions.last(),
+                Some(
+                    Action::Rename(_)
+                        | Action::Copy(_, _)
+                        | Action::Link(_, _, _)
+                        | Action::HardLink(_, _)
+                )
+            );
+        if started {
+            (self.current + 1).min(self.images.len())
+        } else {
+            self.current
+        }
+    }
+
+    /// Queue groups of duplicates, the copies of an image come one after the
+    /// other. New copies of an image already queued come right after the
+    /// last one, unless it was shown already.
+    pub fn add_duplicates(&mut self, groups: Vec<Vec<PathBuf>>) {
+        for (group, new) in self.duplicates.add_groups(groups) {
+            let last = group
+                .iter()
+                .filter_map(|copy| self.images.iter().rposition(|image| image == copy))
+                .max();
+            let index = last
+                .map_or(self.images.len(), |last| last + 1)
+                .max(self.reorder_start());
+            self.images.splice(index..index, new);
+        }
+    }
+
+    /// Keep the current image, and delete its copies left to review
+    pub fn keep_duplicate(&mut self) {
+        let image = match self.current_image() {
+            Some(image) => image,
+            None => return,
+        };
+        let group = match self.duplicates.group(&image) {
+            Some(group) => group.to_vec(),
+            None => return,
+        };
+
+        self.push_action(Action::Skip(image));
+        while let Some(copy) = self.current_image() {
+            if !group.contains(&copy) {
+                break;
+            }
+            self.push_action(Action::Delete(copy));
+        }
+    }
+
+    /// Whether all the other copies of an image are being deleted
+    pub fn is_last_copy(&self, image: &Path) -> bool {
+        self.duplicates.group(image).is_some_and(|group| {
+            group.iter().filter(|copy| *copy != image).all(|copy| {
+                self.actions
+                    .iter()
+                    .any(|action| matches!(action, Action::Delete(deleted) if deleted == copy))
+            })
         })
     }
 
@@ -93,6 +369,168 @@ impl App {
         Some(self.images[self.current].clone())
     }
 
+    pub fn sidecars(&self, image: &Path) -> &[PathBuf] {
+        self.sidecars.get(image).map_or(&[], Vec::as_slice)
+    }
+
+    /// The folders the current image was copied or linked to
+    pub fn copies(&self) -> Vec<&Path> {
+        let image = match self.images.get(self.current) {
+            Some(image) => image,
+            None => return vec![],
+        };
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Copy(copied, target)
+                | Action::Link(copied, target, _)
+                | Action::HardLink(copied, target)
+                    if copied == image =>
+                {
+                    Some(target.as_path())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The name the current image was given, it is kept while copying or
+    /// linking it
+    pub fn pending_rename(&self) -> Option<&str> {
+        let image = self.images.get(self.current)?;
+        for action in self.actions.iter().rev() {
+            match action {
+                Action::Rename(name) => return Some(name),
+                Action::Copy(copied, _)
+                | Action::Link(copied, _, _)
+                | Action::HardLink(copied, _)
+                    if copied == image => {}
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Where the sidecars of an image moved, copied or linked to `target` end
+    /// up. Renamed images take the new name to their sidecars too.
+    pub fn sidecar_moves(&self, image: &Path, target: &Path) -> Vec<(PathBuf, PathBuf)> {
+        let renamed = !self.is_bound(target);
+        self.sidecars(image)
+            .iter()
+            .map(|sidecar| {
+                let target = match sidecar.extension() {
+                    Some(ext) if renamed => target.with_extension(ext),
+                    _ => target.to_path_buf(),
+                };
+                (sidecar.clone(), target)
+            })
+            .collect()
+    }
+
+    fn is_bound(&self, target: &Path) -> bool {
+        self.key_mapping.values().any(|folder| folder == target)
+    }
+
+    // The path of a file moved, copied or linked to `target`
+    fn target_path(&self, file: &Path, target: &Path) -> PathBuf {
+        match file.file_name() {
+            Some(name) if self.is_bound(target) => target.join(name),
+            _ => target.to_path_buf(),
+        }
+    }
+
+    // What a symbolic link at `link` points to, for it to lead to `image`
+    // wherever the script is run from
+    fn link_source(image: &Path, link: &Path, relative: bool) -> PathBuf {
+        let image = path::absolute(image).unwrap_or_else(|_| image.to_path_buf());
+        if !relative {
+            return image;
+        }
+        let folder = link.parent().unwrap_or(link);
+        let folder = path::absolute(folder).unwrap_or_else(|_| folder.to_path_buf());
+
+        let common = image
+            .components()
+            .zip(folder.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut source: PathBuf = folder
+            .components()
+            .skip(common)
+            .map(|_| Component::ParentDir)
+            .collect();
+        source.extend(image.components().skip(common));
+        source
+    }
+
+    /// The shell commands carrying out an action, on sidecars too
+    pub fn commands(&self, action: &Action) -> Vec<String> {
+        let (command, image, target) = match action {
+            Action::MkDir(folder) => return vec![format!("mkdir -p \"{}\"", folder.display())],
+            Action::Delete(image) => {
+                return std::iter::once(image)
+                    .chain(self.sidecars(image))
+                    .map(|image| format!("rm \"{}\"", image.display()))
+                    .collect()
+            }
+            Action::Move(image, target) => ("mv", image, target),
+            Action::Copy(image, target) => ("cp", image, target),
+            Action::Link(image, target, _) => ("ln -s", image, target),
+            Action::HardLink(image, target) => ("ln", image, target),
+            Action::Skip(_) | Action::Rename(_) => return vec![],
+        };
+        std::iter::once((image.clone(), target.clone()))
+            .chain(self.sidecar_moves(image, target))
+            .map(|(file, target)| match action {
+                // Symbolic links point to where the image ends up, as it may
+                // be moved after being linked, but keep the name it had. It
+                // is copied instead if it ends up deleted.
+                Action::Link(_, _, relative) => {
+                    let link = self.target_path(&file, &target);
+                    match self.final_path(image, &file) {
+                        Some(path) => format!(
+                            "{} \"{}\" \"{}\"",
+                            command,
+                            App::link_source(&path, &link, *relative).display(),
+                            link.display()
+                        ),
+                        None => format!("cp \"{}\" \"{}\"", file.display(), link.display()),
+                    }
+                }
+                _ => format!(
+                    "{} \"{}\" \"{}\"",
+                    command,
+                    file.display(),
+                    target.display()
+                ),
+            })
+            .collect()
+    }
+
+    // Where an image, or one of its sidecars, is once the whole script has
+    // run, None if it's deleted
+    fn final_path(&self, image: &Path, file: &Path) -> Option<PathBuf> {
+        let moved_to = self.actions.iter().find_map(|action| match action {
+            Action::Move(moved, target) if moved == image => Some(Some(target)),
+            Action::Delete(deleted) if deleted == image => Some(None),
+            _ => None,
+        });
+        let target = match moved_to {
+            Some(None) => return None,
+            Some(Some(target)) if file == image => target.clone(),
+            Some(Some(target)) => match self
+                .sidecar_moves(image, target)
+                .into_iter()
+                .find(|(sidecar, _)| sidecar == file)
+            {
+                Some((_, target)) => target,
+                None => return Some(file.to_path_buf()),
+            },
+            None => return Some(file.to_path_buf()),
+        };
+        Some(self.target_path(file, &target))
+    }
+
     pub fn pop_action(&mut self) {
         let last_action = self.actions.last().cloned();
 
@@ -163,16 +601,7 @@ impl App {
         let mut lines: Vec<String> = vec!["#!/bin/sh".to_string()];
 
         for action in self.actions.iter() {
-            match action {
-                Action::MkDir(folder) => lines.push(format!("mkdir -p \"{}\"", folder.display())),
-                Action::Move(image_path, folder) => lines.push(format!(
-                    "mv \"{}\" \"{}\"",
-                    image_path.display(),
-                    folder.display()
-                )),
-                Action::Delete(image) => lines.push(format!("rm \"{}\"", image.display())),
-                _ => {}
-            }
+            lines.extend(self.commands(action));
         }
 
         let script = lines.join("\n");
@@ -183,13 +612,15 @@ impl App {
         Ok(())
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn parse_key_mapping(
-        args: Vec<(char, PathBuf)>,
-    ) -> Result<(BTreeMap<char, PathBuf>, Vec<Action>)> {
+        args: Vec<(char, Mode, PathBuf)>,
+    ) -> Result<(BTreeMap<char, PathBuf>, HashMap<char, Mode>, Vec<Action>)> {
         let mut key_mapping = BTreeMap::new();
+        let mut key_modes = HashMap::new();
         let mut actions = vec![];
 
-        for (key, path_buf) in args.into_iter() {
+        for (key, mode, path_buf) in args.into_iter() {
             let path = path_buf.as_path();
 
             if path.exists() && !path.is_dir() {
@@ -203,93 +634,197 @@ impl App {
                 actions.push(Action::MkDir(path_buf.clone()));
             }
 
+            if mode != Mode::Move {
+                key_modes.insert(key, mode);
+            }
             key_mapping.insert(key, path_buf);
         }
 
-        Ok((key_mapping, actions))
+        Ok((key_mapping, key_modes, actions))
     }
 
-    pub fn parse_images(args: Vec<PathBuf>, recurse: bool) -> Vec<PathBuf> {
-        let mut images: Vec<PathBuf> = vec![];
-
-        let mut count = 0u32;
+    /// Replace `-` with the list of paths read from stdin, and add the ones
+    /// listed in `files_from`
+    fn read_inputs(
+        args: Vec<PathBuf>,
+        files_from: Option<PathBuf>,
+        null: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let mut inputs = vec![];
 
         for input in args {
-            if App::is_image(&input) {
-                count += 1;
-                images.push(input);
-            } else if input.is_dir() {
-                images.extend(App::discover_images(&input, recurse, &mut count));
+            if input.as_os_str() == "-" {
+                inputs.extend(App::read_path_list(io::stdin().lock(), null));
+            } else {
+                inputs.push(input);
             }
         }
 
-        images
-    }
-
-    fn discover_images(path: &Path, recurse: bool, count: &mut u32) -> Vec<PathBuf> {
-        let mut images = vec![];
-
-        if !path.is_dir() {
-            return images;
+        if let Some(path) = files_from {
+            let file = File::open(&path)
+                .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+            inputs.extend(App::read_path_list(io::BufReader::new(file), null));
         }
 
-        let entries = match path.read_dir() {
-            Ok(entries) => entries,
-            Err(_) => return images,
-        };
+        Ok(inputs)
+    }
 
-        for entry in entries.flatten() {
-            let path = entry.path();
-
-            if *count >= 500 {
-                // TODO: Add an option for this value
-                // Limit the number of images, to halt a potential runaway
-                // program. The user will probably appreciate working with fewer
-                // images, but at least being able to start the program.
-                //
-                // After having run the output script, the next invocation would
-                // handle the following 500 images,
-                break;
-            }
+    fn read_path_list<R: BufRead>(reader: R, null: bool) -> Vec<PathBuf> {
+        let separator = if null { b'\0' } else { b'\n' };
+        reader
+            .split(separator)
+            .map_while(|line| line.ok())
+            .map(|mut line| {
+                if !null && line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                line
+            })
+            .filter(|line| !line.is_empty())
+            .map(|line| PathBuf::from(OsString::from_vec(line)))
+            .collect()
+    }
 
-            if path.is_dir() && recurse {
-                images.extend(App::discover_images(&path, recurse, count));
-            } else if App::is_image(&path) {
-                *count += 1;
-                images.push(path);
+    /// Pair up RAW files with the JPEG (or any other image) of the same name
+    /// in the same folder, only the latter is displayed. Pairs are looked for
+    /// among all the images found so far, as a batch limit or the watcher may
+    /// split them, and a RAW file already shown stays on its own.
+    fn pair_sidecars(&mut self, images: Vec<PathBuf>) -> Vec<PathBuf> {
+        let is_raw = |path: &Path| Format::from_path(path) == Some(Format::Raw);
+
+        for image in images.iter().filter(|image| !is_raw(image)) {
+            let name = image.with_extension("");
+            if self.primaries.contains_key(&name) {
+                continue;
+            }
+            self.primaries.insert(name.clone(), image.clone());
+
+            if let Some(raw) = self.lone_raws.remove(&name) {
+                let start = self.reorder_start();
+                if let Some(i) = self.images[start..].iter().position(|left| *left == raw) {
+                    self.images.remove(start + i);
+                    self.sidecars.entry(image.clone()).or_default().push(raw);
+                }
             }
         }
 
-        images
+        let mut displayed = vec![];
+        for image in images {
+            let name = image.with_extension("");
+            match self.primaries.get(&name) {
+                Some(primary) if is_raw(&image) => self
+                    .sidecars
+                    .entry(primary.clone())
+                    .or_default()
+                    .push(image),
+                _ => {
+                    if is_raw(&image) {
+                        self.lone_raws.insert(name, image.clone());
+                    }
+                    displayed.push(image);
+                }
+            }
+        }
+        displayed
     }
+}
 
-    fn is_image(path: &Path) -> bool {
-        if !path.is_file() {
-            return false;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
 
-        // first, a quick check for the file extension
-        let image_exts = ["jpeg", "jpg", "png"];
-        let looks_like_image = path.extension().map_or(false, |f| {
-            image_exts.iter().any(|ext| f.to_str() == Some(ext))
+    fn add(app: &mut App, found: &[&str]) {
+        app.add_images(Images {
+            paths: paths(found),
+            existing: HashMap::new(),
         });
-        if !looks_like_image {
-            return false;
-        }
+    }
 
-        // second, check the file's mime type by reading the first few bytes
-        let kind = infer::get_from_path(path);
-        if kind.is_err() {
-            // could not read file
-            return false;
-        }
-        let kind = kind.unwrap();
-        if kind.is_none() {
-            // unknown file type
-            return false;
-        }
+    #[test]
+    fn sidecars_across_batches() {
+        let mut app = App {
+            group_sidecars: true,
+            ..App::default()
+        };
+        add(&mut app, &["a.cr2", "b.jpg", "c.cr2"]);
+        app.push_action(Action::Skip(PathBuf::from("a.cr2")));
+        add(&mut app, &["b.nef", "a.jpg"]);
+        add(&mut app, &["c.jpg"]);
+
+        // a.cr2 was shown on its own before a.jpg was found
+        assert_eq!(app.images, paths(&["a.cr2", "a.jpg", "b.jpg", "c.jpg"]));
+        assert_eq!(app.sidecars(Path::new("a.jpg")), &[] as &[PathBuf]);
+        assert_eq!(app.sidecars(Path::new("b.jpg")), paths(&["b.nef"]));
+        assert_eq!(app.sidecars(Path::new("c.jpg")), paths(&["c.cr2"]));
+    }
+
+    #[test]
+    fn rename_kept_while_copying() {
+        let mut app = App::default();
+        add(&mut app, &["a.jpg", "b.jpg"]);
+        app.push_action(Action::Rename("c.jpg".to_string()));
+        app.push_action(Action::Copy(
+            PathBuf::from("a.jpg"),
+            PathBuf::from("copies/c.jpg"),
+        ));
+        assert_eq!(app.pending_rename(), Some("c.jpg"));
+
+        app.push_action(Action::Move(PathBuf::from("a.jpg"), PathBuf::from("c.jpg")));
+        assert_eq!(app.pending_rename(), None);
+    }
 
-        let kind = kind.unwrap();
-        matches!(kind.mime_type(), "image/jpeg" | "image/png")
+    #[test]
+    fn relative_link_source() {
+        let source = App::link_source(Path::new("/a/b/c.jpg"), Path::new("/a/d/e/c.jpg"), true);
+        assert_eq!(source, PathBuf::from("../../b/c.jpg"));
+        let source = App::link_source(Path::new("/a/b/c.jpg"), Path::new("/a/b/l.jpg"), true);
+        assert_eq!(source, PathBuf::from("c.jpg"));
+        let source = App::link_source(Path::new("/a/b/c.jpg"), Path::new("/d/l.jpg"), false);
+        assert_eq!(source, PathBuf::from("/a/b/c.jpg"));
+    }
+
+    #[test]
+    fn link_then_rename_and_move() {
+        let mut app = App {
+            key_mapping: BTreeMap::from([('f', PathBuf::from("/fav")), ('m', PathBuf::from("/m"))]),
+            ..App::default()
+        };
+        add(&mut app, &["/in/a.jpg", "/in/b.jpg"]);
+        app.push_action(Action::Rename("c.jpg".to_string()));
+        app.push_action(Action::Link(
+            PathBuf::from("/in/a.jpg"),
+            PathBuf::from("/fav/c.jpg"),
+            false,
+        ));
+        app.push_action(Action::Copy(
+            PathBuf::from("/in/a.jpg"),
+            PathBuf::from("/fav"),
+        ));
+        app.push_action(Action::Move(
+            PathBuf::from("/in/a.jpg"),
+            PathBuf::from("/m/c.jpg"),
+        ));
+        app.push_action(Action::Link(
+            PathBuf::from("/in/b.jpg"),
+            PathBuf::from("/fav"),
+            false,
+        ));
+        app.push_action(Action::Delete(PathBuf::from("/in/b.jpg")));
+
+        let script: Vec<String> = app.actions.iter().flat_map(|a| app.commands(a)).collect();
+        assert_eq!(
+            script,
+            [
+                "ln -s \"/m/c.jpg\" \"/fav/c.jpg\"",
+                "cp \"/in/a.jpg\" \"/fav\"",
+                "mv \"/in/a.jpg\" \"/m/c.jpg\"",
+                "cp \"/in/b.jpg\" \"/fav/b.jpg\"",
+                "rm \"/in/b.jpg\"",
+            ]
+        );
     }
 }
@@ -0,0 +1,127 @@
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+// MP4, QuickTime, HEIF and AVIF files are all made of nested boxes, each one
+// starting with its size and kind
+
+/// Find a box of the given kind between start and end, returning where its
+/// content starts and ends
+pub fn find_box(file: &mut File, start: u64, end: u64, kind: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut offset = start;
+    while let Some((found, content, box_end)) = next_box(file, offset, end) {
+        if &found == kind {
+            return Some((content, box_end));
+        }
+        offset = box_end;
+    }
+    None
+}
+
+/// The box at offset, if it ends before end: its kind, and where its content
+/// starts and ends. Sizes come from the file, so a corrupt one ends the search
+/// rather than overflowing.
+pub fn next_box(file: &mut File, offset: u64, end: u64) -> Option<([u8; 4], u64, u64)> {
+    if offset.checked_add(8)? > end {
+        return None;
+    }
+    let header = read_at(file, offset, 16)?;
+    let (size, header_size) = match be_u32(&header[..4]) {
+        // Extends to the end of the file
+        0 => (end - offset, 8),
+        // 64 bit size
+        1 => (be_u64(&header[8..16]), 16),
+        size => (size as u64, 8),
+    };
+    if size < header_size {
+        return None;
+    }
+    let box_end = offset.checked_add(size)?;
+    let kind = header[4..8].try_into().unwrap();
+    Some((kind, offset + header_size, box_end.min(end)))
+}
+
+/// Read length bytes at offset, padded with zeros past the end of the file,
+/// which must not end within the first 8 bytes
+pub fn read_at(file: &mut File, offset: u64, length: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0; length];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let read = file.read(&mut buf).ok()?;
+    if read < 8.min(length) {
+        return None;
+    }
+    Some(buf)
+}
+
+pub fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes(bytes[..2].try_into().unwrap())
+}
+
+pub fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+pub fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn temp_file(name: &str, bytes: &[u8]) -> File {
+        let path = env::temp_dir().join(format!("image-sorter-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let file = File::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    fn boxed(size: u32, kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        [&size.to_be_bytes()[..], kind, content].concat()
+    }
+
+    #[test]
+    fn boxes() {
+        let bytes = [
+            boxed(12, b"ftyp", b"isom"),
+            // 64 bit size, after the kind
+            boxed(1, b"free", &[&20u64.to_be_bytes()[..], b"abcd"].concat()),
+            // Extends to the end of the file
+            boxed(0, b"mdat", b"data"),
+        ]
+        .concat();
+        let mut file = temp_file("boxes.mp4", &bytes);
+        let end = bytes.len() as u64;
+
+        assert_eq!(find_box(&mut file, 0, end, b"ftyp"), Some((8, 12)));
+        assert_eq!(find_box(&mut file, 0, end, b"free"), Some((28, 32)));
+        assert_eq!(find_box(&mut file, 0, end, b"mdat"), Some((40, 44)));
+        assert_eq!(find_box(&mut file, 0, end, b"moov"), None);
+    }
+
+    #[test]
+    fn corrupt_boxes() {
+        // Smaller than its own header
+        let mut file = temp_file("small.mp4", &boxed(4, b"moov", b"abcd"));
+        assert_eq!(next_box(&mut file, 0, 12), None);
+
+        // Past the end of the file, or of the box it's in
+        let mut file = temp_file("large.mp4", &boxed(1000, b"moov", b"abcd"));
+        assert_eq!(next_box(&mut file, 0, 12), Some((*b"moov", 8, 12)));
+
+        // A 64 bit size that would overflow the offset
+        let bytes = [
+            boxed(8, b"free", b""),
+            boxed(1, b"moov", &u64::MAX.to_be_bytes()),
+        ]
+        .concat();
+        let mut file = temp_file("overflow.mp4", &bytes);
+        assert_eq!(next_box(&mut file, 8, 24), None);
+        assert_eq!(next_box(&mut file, u64::MAX - 4, u64::MAX), None);
+        assert_eq!(find_box(&mut file, 0, 24, b"trak"), None);
+    }
+}
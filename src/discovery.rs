@@ -0,0 +1,496 @@
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    num::NonZeroUsize,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::duplicates::{Destinations, Existing};
+use crate::event::Event;
+use crate::filter::Filter;
+use crate::format::{Format, Formats};
+use crate::order::Order;
+use crate::raw;
+
+// Images found are handed over to the app at most this often, rather than
+// redrawing for every folder. Walkers hand over what they found so far in a
+// folder as often.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+// Folders on network shares are mostly waited on, so there are at least a
+// few walkers whatever the number of CPUs
+const MIN_WALKERS: usize = 4;
+const MAX_WALKERS: usize = 16;
+
+/// Most images to discover at once, to halt a potential runaway program.
+/// The user will probably appreciate working with fewer images, but at least
+/// being able to start the program.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Limit(Option<usize>);
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit(Some(500))
+    }
+}
+
+impl FromStr for Limit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" | "unlimited" | "0" => Ok(Limit(None)),
+            _ => s
+                .parse()
+                .map(|limit| Limit(Some(limit)))
+                .map_err(|_| anyhow!("invalid limit `{}`", s)),
+        }
+    }
+}
+
+/// Which symbolic links are followed when looking into folders, the input
+/// ones always are
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Symlinks {
+    None,
+    Files,
+    #[default]
+    All,
+}
+
+impl Symlinks {
+    pub const VARIANTS: &'static [&'static str] = &["none", "files", "all"];
+
+    pub fn allows(self, path: &Path, is_dir: bool) -> bool {
+        let is_symlink = path
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink());
+        match self {
+            _ if !is_symlink => true,
+            Symlinks::None => false,
+            Symlinks::Files => !is_dir,
+            Symlinks::All => true,
+        }
+    }
+}
+
+impl FromStr for Symlinks {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Symlinks::None),
+            "files" => Ok(Symlinks::Files),
+            "all" => Ok(Symlinks::All),
+            _ => Err(anyhow!("unknown symlink policy `{}`", s)),
+        }
+    }
+}
+
+/// Device and inode of a file, the same for all its hard links and the
+/// symbolic links to it
+pub type FileId = (u64, u64);
+
+pub fn file_id(path: &Path) -> Option<FileId> {
+    path.metadata()
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+/// Files found so far, so each one is sorted once whatever the paths leading
+/// to it
+#[derive(Default, Clone)]
+pub struct Found(Arc<Mutex<HashSet<FileId>>>);
+
+impl Found {
+    /// Whether the file wasn't found before
+    pub fn insert(&self, path: &Path) -> bool {
+        self.insert_id(file_id(path))
+    }
+
+    fn contains_id(&self, id: FileId) -> bool {
+        self.0.lock().unwrap().contains(&id)
+    }
+
+    // Files that can't be told apart are always new
+    fn insert_id(&self, id: Option<FileId>) -> bool {
+        id.is_none_or(|id| self.0.lock().unwrap().insert(id))
+    }
+}
+
+/// Images found, and the identical copies of some of them already in a bound
+/// folder
+#[derive(Default)]
+pub struct Images {
+    pub paths: Vec<PathBuf>,
+    pub existing: HashMap<PathBuf, PathBuf>,
+}
+
+impl Images {
+    /// An image, unless its copy in a bound folder means it is skipped
+    pub fn push(&mut self, path: PathBuf, destinations: &Destinations) {
+        match destinations.existing_copy(&path) {
+            Some(_) if destinations.existing == Existing::Skip => return,
+            Some(copy) => {
+                self.existing.insert(path.clone(), copy);
+            }
+            None => {}
+        }
+        self.paths.push(path);
+    }
+}
+
+/// Where images are discovered, and how far discovery got
+#[derive(Default)]
+pub struct Discovery {
+    pub inputs: Vec<PathBuf>,
+    pub recurse: bool,
+    pub filter: Arc<Filter>,
+    pub formats: Formats,
+    pub limit: Limit,
+    pub symlinks: Symlinks,
+    pub order: Order,
+    pub destinations: Destinations,
+    /// Where found images are sent to, as events
+    pub events: Option<Sender<Event>>,
+    pub found: Found,
+    /// Whether images are being looked for right now
+    pub running: bool,
+    /// Whether the limit was hit, leaving images for the next batch
+    pub truncated: bool,
+}
+
+impl Discovery {
+    /// Look for the next batch of images in the background, skipping the
+    /// ones already found. They are sent as `Event::Discovered` as they come,
+    /// then `Event::DiscoveryDone` tells whether the limit was hit.
+    pub fn start(&mut self) {
+        let events = match &self.events {
+            Some(events) if !self.running => events.clone(),
+            _ => return,
+        };
+        self.running = true;
+        self.truncated = false;
+
+        let walk = Arc::new(Walk {
+            recurse: self.recurse,
+            filter: self.filter.clone(),
+            formats: self.formats.clone(),
+            symlinks: self.symlinks,
+            order: self.order.clone(),
+            destinations: self.destinations.clone(),
+            found: self.found.clone(),
+            jobs: AtomicUsize::new(0),
+            done: AtomicBool::new(false),
+        });
+        let (inputs, limit) = (self.inputs.clone(), self.limit);
+        thread::spawn(move || walk.run(inputs, limit, events));
+    }
+}
+
+// A batch being discovered. Folders are read ahead by the walkers in
+// parallel, but their images are taken in path order, so the same ones fill
+// a batch whichever walker gets to them first.
+struct Walk {
+    recurse: bool,
+    filter: Arc<Filter>,
+    formats: Formats,
+    symlinks: Symlinks,
+    order: Order,
+    destinations: Destinations,
+    found: Found,
+    // Paths handed to the walkers so far, each one is known by its number
+    jobs: AtomicUsize,
+    // Whether the batch is complete, or the app is gone
+    done: AtomicBool,
+}
+
+// A path for a walker to look into, with the folders leading to it, as a
+// symbolic link may lead back to one of them
+struct Job {
+    id: usize,
+    path: PathBuf,
+    ancestors: Vec<FileId>,
+}
+
+// What a walker found at a path, in name order
+enum Entry {
+    Image {
+        path: PathBuf,
+        id: Option<FileId>,
+        limited: bool,
+        // Its identical copy already in a bound folder
+        existing: Option<PathBuf>,
+    },
+    // A folder being looked into, by job number
+    Folder(usize),
+}
+
+// Part of what a walker found for a job, the entries of large folders take
+// a while to look into and are handed over as they come
+struct Listing {
+    job: usize,
+    entries: Vec<Entry>,
+    // Whether the job is complete
+    last: bool,
+}
+
+impl Walk {
+    fn run(self: Arc<Self>, inputs: Vec<PathBuf>, limit: Limit, events: Sender<Event>) {
+        let (jobs_tx, jobs_rx) = unbounded::<Job>();
+        let (listings_tx, listings_rx) = unbounded::<Listing>();
+
+        let inputs: Vec<Entry> = inputs
+            .into_iter()
+            .map(|input| self.queue(&jobs_tx, input, vec![]))
+            .collect();
+
+        let walkers = thread::available_parallelism()
+            .map_or(MIN_WALKERS, NonZeroUsize::get)
+            .clamp(MIN_WALKERS, MAX_WALKERS);
+        for _ in 0..walkers {
+            let walk = self.clone();
+            let (jobs_tx, jobs_rx, listings_tx) =
+                (jobs_tx.clone(), jobs_rx.clone(), listings_tx.clone());
+            thread::spawn(move || walk.walk(jobs_tx, jobs_rx, listings_tx));
+        }
+        // Only the walkers hold senders now, listings_rx disconnects if they
+        // are all gone
+        drop((jobs_tx, listings_tx));
+
+        // Entries not taken yet, by job. The ones being taken are on the stack
+        // along with their job, until it's complete.
+        let mut listings: HashMap<usize, VecDeque<Listing>> = HashMap::new();
+        let mut stack = vec![(None, inputs.into_iter())];
+        let mut images = Images::default();
+        let mut flushed = Instant::now();
+        let mut count = 0;
+        let mut truncated = false;
+        while let Some((job, entries)) = stack.last_mut() {
+            match entries.next() {
+                None => {
+                    let id = match *job {
+                        Some(id) => id,
+                        None => {
+                            stack.pop();
+                            continue;
+                        }
+                    };
+                    // Images taken so far are sent while waiting for more of
+                    // the folder to be read
+                    let listing = loop {
+                        if let Some(listing) = listings.get_mut(&id).and_then(VecDeque::pop_front) {
+                            break listing;
+                        }
+                        match listings_rx.recv_timeout(FLUSH_INTERVAL) {
+                            Ok(listing) => {
+                                listings.entry(listing.job).or_default().push_back(listing);
+                            }
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                        if !self.flush(&mut images, &mut flushed, &events) {
+                            return;
+                        }
+                    };
+                    if listing.last {
+                        listings.remove(&id);
+                        *job = None;
+                    }
+                    *entries = listing.entries.into_iter();
+                }
+                Some(Entry::Folder(job)) => {
+                    stack.push((Some(job), vec![].into_iter()));
+                }
+                Some(Entry::Image {
+                    path,
+                    id,
+                    limited,
+                    existing,
+                }) => {
+                    if id.is_some_and(|id| self.found.contains_id(id)) {
+                        continue;
+                    }
+                    // Only stop once there is an image left, so the next
+                    // batch isn't empty. Images given as inputs count, but
+                    // aren't limited.
+                    if limited && limit.0.is_some_and(|limit| count >= limit) {
+                        truncated = true;
+                        break;
+                    }
+                    if self.found.insert_id(id) {
+                        if let Some(copy) = existing {
+                            images.existing.insert(path.clone(), copy);
+                        }
+                        images.paths.push(path);
+                        count += 1;
+                    }
+                }
+            }
+            if !self.flush(&mut images, &mut flushed, &events) {
+                return;
+            }
+        }
+        self.done.store(true, Ordering::SeqCst);
+
+        if !images.paths.is_empty() {
+            events.send(Event::Discovered(images)).ok();
+        }
+        events.send(Event::DiscoveryDone(truncated)).ok();
+    }
+
+    // Send the images taken once in a while, false if the app is gone
+    fn flush(&self, images: &mut Images, flushed: &mut Instant, events: &Sender<Event>) -> bool {
+        if images.paths.is_empty() || flushed.elapsed() < FLUSH_INTERVAL {
+            return true;
+        }
+        *flushed = Instant::now();
+        if events.send(Event::Discovered(mem::take(images))).is_err() {
+            self.done.store(true, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    fn queue(&self, jobs_tx: &Sender<Job>, path: PathBuf, ancestors: Vec<FileId>) -> Entry {
+        let id = self.jobs.fetch_add(1, Ordering::SeqCst);
+        jobs_tx
+            .send(Job {
+                id,
+                path,
+                ancestors,
+            })
+            .ok();
+        Entry::Folder(id)
+    }
+
+    // Take jobs until the batch is complete, folders found along the way are
+    // queued to be taken by any walker
+    fn walk(&self, jobs_tx: Sender<Job>, jobs_rx: Receiver<Job>, listings_tx: Sender<Listing>) {
+        while !self.done.load(Ordering::SeqCst) {
+            let job = match jobs_rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(job) => job,
+                Err(_) => continue,
+            };
+            self.list(&job, &jobs_tx, &listings_tx);
+        }
+    }
+
+    fn list(&self, job: &Job, jobs_tx: &Sender<Job>, listings_tx: &Sender<Listing>) {
+        let mut entries = vec![];
+        let send = |entries, last| {
+            listings_tx
+                .send(Listing {
+                    job: job.id,
+                    entries,
+                    last,
+                })
+                .ok();
+        };
+
+        // Only inputs can be files, they go through the filter all the same
+        // as lists made by other tools may have anything in them
+        if !job.path.is_dir() {
+            if self.filter.allows(&job.path, false) && is_image(&job.path, &self.formats) {
+                entries.extend(self.image(job.path.clone(), file_id(&job.path), false));
+            }
+            return send(entries, true);
+        }
+
+        let id = file_id(&job.path);
+        if id.is_some_and(|id| job.ancestors.contains(&id)) {
+            return send(entries, true);
+        }
+        let mut ancestors = job.ancestors.clone();
+        ancestors.extend(id);
+
+        let mut paths: Vec<PathBuf> = match job.path.read_dir() {
+            Ok(dir) => dir.flatten().map(|entry| entry.path()).collect(),
+            Err(_) => return send(entries, true),
+        };
+        paths.sort();
+
+        let mut sent = Instant::now();
+        for path in paths {
+            if self.done.load(Ordering::SeqCst) {
+                break;
+            }
+            if !entries.is_empty() && sent.elapsed() >= FLUSH_INTERVAL {
+                send(mem::take(&mut entries), false);
+                sent = Instant::now();
+            }
+
+            let is_dir = path.is_dir();
+            if !self.filter.allows(&path, is_dir) || !self.symlinks.allows(&path, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                if self.recurse {
+                    entries.push(self.queue(jobs_tx, path, ancestors.clone()));
+                }
+                continue;
+            }
+            let id = file_id(&path);
+            if id.is_some_and(|id| self.found.contains_id(id)) || !is_image(&path, &self.formats) {
+                continue;
+            }
+            entries.extend(self.image(path, id, true));
+        }
+
+        send(entries, true)
+    }
+
+    // Images are looked into as they are found, by whatever walker did,
+    // rather than by the app
+    fn image(&self, path: PathBuf, id: Option<FileId>, limited: bool) -> Option<Entry> {
+        let existing = self.destinations.existing_copy(&path);
+        if existing.is_some() && self.destinations.existing == Existing::Skip {
+            return None;
+        }
+        self.order.prepare(&path);
+        Some(Entry::Image {
+            path,
+            id,
+            limited,
+            existing,
+        })
+    }
+}
+
+pub fn is_image(path: &Path, formats: &Formats) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+
+    // first, a quick check for the file extension
+    let format = match Format::from_path(path) {
+        Some(format) if formats.contains(format) => format,
+        _ => return false,
+    };
+
+    // second, check the file's mime type by reading the first few bytes
+    let kind = infer::get_from_path(path);
+    if kind.is_err() {
+        // could not read file
+        return false;
+    }
+    let kind = kind.unwrap();
+    if kind.is_none() {
+        // unknown file type
+        return format == Format::Raw && raw::is_raf(path);
+    }
+
+    let kind = kind.unwrap();
+    format.mime_types().contains(&kind.mime_type())
+}
@@ -0,0 +1,311 @@
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{unbounded, Sender};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io, mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+};
+
+use crate::discovery::file_id;
+use crate::event::Event;
+use crate::order::Order;
+
+/// Byte identical images, reviewed one group after the other
+#[derive(Default)]
+pub struct Duplicates {
+    /// Whether only duplicates are sorted
+    pub enabled: bool,
+    /// Whether images are being compared right now
+    pub hashing: bool,
+    groups: Vec<Vec<PathBuf>>,
+    group_of: HashMap<PathBuf, usize>,
+    // Images discovered, to be compared once the batch is over
+    pending: Vec<PathBuf>,
+    // Where images are sent to be compared with all the ones before them,
+    // and how many times they were
+    hasher: Option<Sender<Vec<PathBuf>>>,
+    waiting: usize,
+}
+
+impl Duplicates {
+    pub fn new(enabled: bool) -> Self {
+        Duplicates {
+            enabled,
+            ..Duplicates::default()
+        }
+    }
+
+    pub fn queue(&mut self, images: Vec<PathBuf>) {
+        self.pending.extend(images);
+    }
+
+    /// Take the groups found by the hashing thread. Groups already known
+    /// may have grown with copies from a later batch, or from the watcher.
+    /// Returns the copies that weren't in any group yet, along with their
+    /// whole group.
+    pub fn add_groups(&mut self, groups: Vec<Vec<PathBuf>>) -> Vec<(Vec<PathBuf>, Vec<PathBuf>)> {
+        self.waiting = self.waiting.saturating_sub(1);
+        self.hashing = self.waiting > 0;
+
+        let mut added = vec![];
+        for group in groups {
+            let new: Vec<PathBuf> = group
+                .iter()
+                .filter(|image| !self.group_of.contains_key(*image))
+                .cloned()
+                .collect();
+            if new.is_empty() {
+                continue;
+            }
+
+            let index = group
+                .iter()
+                .find_map(|image| self.group_of.get(image).copied())
+                .unwrap_or(self.groups.len());
+            for image in new.iter() {
+                self.group_of.insert(image.clone(), index);
+            }
+            if index == self.groups.len() {
+                self.groups.push(group.clone());
+            } else {
+                self.groups[index] = group.clone();
+            }
+            added.push((group, new));
+        }
+        added
+    }
+
+    /// The copies of an image, itself included
+    pub fn group(&self, image: &Path) -> Option<&[PathBuf]> {
+        self.group_of
+            .get(image)
+            .map(|&group| self.groups[group].as_slice())
+    }
+
+    /// Compare the images queued in the background, with each other and the
+    /// ones compared before. Groups of duplicates they are in are sent as
+    /// `Event::Duplicates`.
+    pub fn start(&mut self, order: &Order, events: Sender<Event>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut images = mem::take(&mut self.pending);
+        order.sort(&mut images);
+
+        let hasher = self.hasher.get_or_insert_with(|| {
+            let (tx, rx) = unbounded::<Vec<PathBuf>>();
+            thread::spawn(move || {
+                let mut compared = Compared::default();
+                for images in rx {
+                    let groups = compared.add(images);
+                    if events.send(Event::Duplicates(groups)).is_err() {
+                        break;
+                    }
+                }
+            });
+            tx
+        });
+        if hasher.send(images).is_ok() {
+            self.waiting += 1;
+            self.hashing = true;
+        }
+    }
+}
+
+/// What to do with images that have an identical copy of the same name in
+/// a bound folder, likely sorted already
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Existing {
+    Ignore,
+    #[default]
+    Warn,
+    Skip,
+}
+
+impl Existing {
+    pub const VARIANTS: &'static [&'static str] = &["ignore", "warn", "skip"];
+}
+
+impl FromStr for Existing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ignore" => Ok(Existing::Ignore),
+            "warn" => Ok(Existing::Warn),
+            "skip" => Ok(Existing::Skip),
+            _ => Err(anyhow!("unknown way to handle existing images `{}`", s)),
+        }
+    }
+}
+
+/// The bound folders images are looked for in as they are discovered
+#[derive(Debug, Clone, Default)]
+pub struct Destinations {
+    pub existing: Existing,
+    pub folders: Vec<PathBuf>,
+}
+
+impl Destinations {
+    /// The identical copy of an image in a bound folder, if looked for
+    pub fn existing_copy(&self, image: &Path) -> Option<PathBuf> {
+        match self.existing {
+            Existing::Ignore => None,
+            _ => existing_copy(image, self.folders.iter()),
+        }
+    }
+}
+
+/// The identical copy of an image, with the same name, in any of the folders
+pub fn existing_copy<'a>(
+    image: &Path,
+    mut folders: impl Iterator<Item = &'a PathBuf>,
+) -> Option<PathBuf> {
+    let name = image.file_name()?;
+    let size = image.metadata().ok()?.len();
+    folders.find_map(|folder| {
+        let copy = folder.join(name);
+        let same_size = copy
+            .metadata()
+            .is_ok_and(|m| m.is_file() && m.len() == size);
+        // The image itself, when it is already in that folder, whatever the
+        // paths leading to it
+        if !same_size || file_id(&copy) == file_id(image) {
+            return None;
+        }
+        let hash = content_hash(image).ok()?;
+        (content_hash(&copy).ok()? == hash).then_some(copy)
+    })
+}
+
+// Files compared so far, in the order they were given, by size. Only the
+// ones with a size in common are hashed, once.
+#[derive(Default)]
+struct Compared {
+    position: HashMap<PathBuf, usize>,
+    by_size: HashMap<u64, Vec<PathBuf>>,
+    hashes: HashMap<PathBuf, blake3::Hash>,
+}
+
+impl Compared {
+    // Groups of identical files the new ones are in, whole
+    fn add(&mut self, images: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+        let mut sizes = HashSet::new();
+        for image in images {
+            if self.position.contains_key(&image) {
+                continue;
+            }
+            if let Ok(metadata) = image.metadata() {
+                self.position.insert(image.clone(), self.position.len());
+                self.by_size.entry(metadata.len()).or_default().push(image);
+                sizes.insert(metadata.len());
+            }
+        }
+
+        let same_size = || {
+            sizes
+                .iter()
+                .map(|size| &self.by_size[size])
+                .filter(|same_size| same_size.len() > 1)
+                .flatten()
+        };
+        let candidates: Vec<PathBuf> = same_size()
+            .filter(|image| !self.hashes.contains_key(*image))
+            .cloned()
+            .collect();
+        let hashed = map_parallel(candidates, |path| content_hash(path).ok());
+
+        let mut by_hash: HashMap<blake3::Hash, Vec<PathBuf>> = HashMap::new();
+        for image in same_size() {
+            if let Some(hash) = self.hashes.get(image) {
+                by_hash.entry(*hash).or_default().push(image.clone());
+            }
+        }
+        for (image, hash) in hashed {
+            by_hash.entry(hash).or_default().push(image.clone());
+            self.hashes.insert(image, hash);
+        }
+
+        let position = &self.position;
+        let mut groups: Vec<Vec<PathBuf>> = by_hash
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        for group in groups.iter_mut() {
+            group.sort_by_key(|image| position[image]);
+        }
+        groups.sort_by_key(|group| position[&group[0]]);
+        groups
+    }
+}
+
+/// Hash of the content of a file
+pub fn content_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Compute something for each file on as many threads as there are CPUs,
+/// the files it can't be computed for are left out
+pub fn map_parallel<T: Send>(paths: Vec<PathBuf>, f: fn(&Path) -> Option<T>) -> Vec<(PathBuf, T)> {
+    if paths.is_empty() {
+        return vec![];
+    }
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = paths.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|path| Some((path.clone(), f(path)?)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn duplicates_across_batches() {
+        let folder =
+            env::temp_dir().join(format!("image-sorter-test-{}-duplicates", process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let file = |name: &str, content: &[u8]| {
+            let path = folder.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let a = file("a.jpg", b"first");
+        let b = file("b.jpg", b"other");
+        let c = file("c.jpg", b"first");
+        let d = file("d.jpg", b"first");
+
+        let mut compared = Compared::default();
+        assert!(compared.add(vec![a.clone(), b.clone()]).is_empty());
+        assert_eq!(
+            compared.add(vec![c.clone()]),
+            vec![vec![a.clone(), c.clone()]]
+        );
+        assert_eq!(compared.add(vec![d.clone()]), vec![vec![a, c, d]]);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
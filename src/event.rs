@@ -1,39 +1,87 @@
 use anyhow::{anyhow, Result};
-use crossbeam_channel::{unbounded, Receiver};
-use std::{io, thread, time::Duration};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
 use termion::{event::Key, input::TermRead};
 
+use crate::discovery::Images;
+
 pub enum Event {
     Input(Key),
     Tick,
+    /// Images found in the background
+    Discovered(Images),
+    /// Discovery is over, and whether it stopped at the limit
+    DiscoveryDone(bool),
+    /// Groups of identical images
+    Duplicates(Vec<Vec<PathBuf>>),
+    /// Every image left, clustered by how they look
+    Clusters(Vec<Vec<PathBuf>>),
 }
 
 pub struct EventsListener {
+    tx: Sender<Event>,
     rx: Receiver<Event>,
+    wake_tx: Sender<Instant>,
 }
 
 impl EventsListener {
-    pub fn new(tick_rate: Duration) -> Self {
+    pub fn new(tick_rate: Duration) -> Result<Self> {
         let (tx, rx) = unbounded::<Event>();
-        let tx_clone = tx.clone();
+        let (wake_tx, wake_rx) = unbounded::<Instant>();
+        let (key_tx, tick_tx) = (tx.clone(), tx.clone());
 
+        // Keys are read from the terminal itself, stdin may be a list of
+        // images
+        let tty = termion::get_tty().map_err(|e| anyhow!("could not open the terminal: {}", e))?;
         thread::spawn(move || {
-            let stdin = io::stdin();
-            for key in stdin.keys().flatten() {
-                tx.send(Event::Input(key)).unwrap();
+            for key in tty.keys().flatten() {
+                key_tx.send(Event::Input(key)).unwrap();
             }
         });
-        thread::spawn(move || loop {
-            if tx_clone.send(Event::Tick).is_err() {
-                break;
+        thread::spawn(move || {
+            let mut next_tick = Instant::now();
+            let mut wake: Option<Instant> = None;
+            loop {
+                let now = Instant::now();
+                if now >= next_tick || wake.is_some_and(|at| now >= at) {
+                    if tick_tx.send(Event::Tick).is_err() {
+                        break;
+                    }
+                    if now >= next_tick {
+                        next_tick = now + tick_rate;
+                    }
+                    wake = wake.filter(|&at| at > now);
+                    continue;
+                }
+
+                let until = wake.map_or(next_tick, |at| at.min(next_tick));
+                match wake_rx.recv_timeout(until - now) {
+                    Ok(at) => wake = Some(wake.map_or(at, |wake| wake.min(at))),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
             }
-            thread::sleep(tick_rate);
         });
 
-        EventsListener { rx }
+        Ok(EventsListener { tx, rx, wake_tx })
     }
 
     pub fn next(&self) -> Result<Event> {
         self.rx.recv().map_err(|e| anyhow!(e))
     }
+
+    /// Where other threads send their events
+    pub fn sender(&self) -> Sender<Event> {
+        self.tx.clone()
+    }
+
+    /// Get an extra tick at the given time, e.g. for the next frame of an
+    /// animation, on top of the regular ones
+    pub fn wake_at(&self, at: Instant) {
+        self.wake_tx.send(at).ok();
+    }
 }
@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+use std::path::{Path, PathBuf};
+
+/// Which files and folders are looked into when discovering images
+#[derive(Default)]
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    ignore: Option<Gitignore>,
+}
+
+impl Filter {
+    /// Without includes every image is included. The ignore file follows
+    /// the gitignore syntax, with paths relative to the folder it is in.
+    pub fn new(
+        include: Vec<Glob>,
+        exclude: Vec<Glob>,
+        ignore_file: Option<PathBuf>,
+    ) -> Result<Self> {
+        let ignore = match ignore_file {
+            Some(path) => match Gitignore::new(&path) {
+                (_, Some(e)) => return Err(anyhow!("could not read {}: {}", path.display(), e)),
+                (ignore, None) => Some(ignore),
+            },
+            None => None,
+        };
+
+        Ok(Filter {
+            include: glob_set(include)?,
+            exclude: glob_set(exclude)?,
+            ignore,
+        })
+    }
+
+    /// Whether to look into a folder, or consider a file as an image. Globs
+    /// match either the name or the whole path, e.g. `@eaDir` skips those
+    /// folders anywhere.
+    pub fn allows(&self, path: &Path, is_dir: bool) -> bool {
+        let matches = |globs: &GlobSet| {
+            globs.is_match(path) || path.file_name().is_some_and(|name| globs.is_match(name))
+        };
+
+        if self.exclude.as_ref().is_some_and(matches) {
+            return false;
+        }
+        if let Some(ignore) = &self.ignore {
+            if ignore.matched(path, is_dir).is_ignore() {
+                return false;
+            }
+        }
+        // Includes pick files, folders are always looked into
+        is_dir || self.include.as_ref().is_none_or(matches)
+    }
+}
+
+fn glob_set(globs: Vec<Glob>) -> Result<Option<GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob);
+    }
+    Ok(Some(builder.build()?))
+}
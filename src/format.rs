@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+/// Image formats that can be sorted, video clips included
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Format {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Avif,
+    Heic,
+    Raw,
+    Video,
+}
+
+impl Format {
+    pub const ALL: [Format; 10] = [
+        Format::Jpeg,
+        Format::Png,
+        Format::Gif,
+        Format::Webp,
+        Format::Bmp,
+        Format::Tiff,
+        Format::Avif,
+        Format::Heic,
+        Format::Raw,
+        Format::Video,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Jpeg => "jpeg",
+            Format::Png => "png",
+            Format::Gif => "gif",
+            Format::Webp => "webp",
+            Format::Bmp => "bmp",
+            Format::Tiff => "tiff",
+            Format::Avif => "avif",
+            Format::Heic => "heic",
+            Format::Raw => "raw",
+            Format::Video => "video",
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Jpeg => &["jpeg", "jpg"],
+            Format::Png => &["png"],
+            Format::Gif => &["gif"],
+            Format::Webp => &["webp"],
+            Format::Bmp => &["bmp"],
+            Format::Tiff => &["tiff", "tif"],
+            Format::Avif => &["avif"],
+            Format::Heic => &["heic", "heif"],
+            Format::Raw => &["cr2", "nef", "arw", "dng", "raf"],
+            Format::Video => &["mp4", "m4v", "mov", "mkv", "webm", "avi"],
+        }
+    }
+
+    /// Mime types detected from the content of the file. AVIF is a flavour
+    /// of HEIF, some encoders don't tell them apart. Most RAW formats are
+    /// TIFF based, except for Fujifilm RAF which is unknown to infer.
+    pub fn mime_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Jpeg => &["image/jpeg"],
+            Format::Png => &["image/png"],
+            Format::Gif => &["image/gif"],
+            Format::Webp => &["image/webp"],
+            Format::Bmp => &["image/bmp"],
+            Format::Tiff => &["image/tiff"],
+            Format::Avif => &["image/avif", "image/heif"],
+            Format::Heic => &["image/heif"],
+            Format::Raw => &["image/x-canon-cr2", "image/tiff"],
+            Format::Video => &[
+                "video/mp4",
+                "video/x-m4v",
+                "video/quicktime",
+                "video/x-matroska",
+                "video/webm",
+                "video/x-msvideo",
+            ],
+        }
+    }
+
+    /// Whether the image crate can't decode it, and libheif has to
+    pub fn needs_libheif(self) -> bool {
+        matches!(self, Format::Avif | Format::Heic)
+    }
+
+    /// Whether w3mimgdisplay can draw the file as is
+    pub fn w3m_native(self) -> bool {
+        matches!(self, Format::Jpeg | Format::Png | Format::Gif | Format::Bmp)
+    }
+
+    /// Format of a file, going by its extension
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| format.extensions().contains(&ext.as_str()))
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.to_lowercase();
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s || format.extensions().contains(&s.as_str()))
+            .ok_or_else(|| anyhow!("unknown format `{}`", s))
+    }
+}
+
+/// The set of formats to sort
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Formats(BTreeSet<Format>);
+
+impl Formats {
+    pub fn contains(&self, format: Format) -> bool {
+        self.0.contains(&format)
+    }
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        Formats(Format::ALL.iter().copied().collect())
+    }
+}
+
+/// A comma separated list of formats, e.g. `jpeg,png` sorts only those.
+/// Prefixing them with `+` or `-` adds them to or removes them from the
+/// default set instead, e.g. `-gif,-tiff`.
+impl FromStr for Formats {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let entries: Vec<&str> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .collect();
+        let relative = entries
+            .first()
+            .is_some_and(|e| e.starts_with('+') || e.starts_with('-'));
+        let mut formats = if relative {
+            Formats::default()
+        } else {
+            Formats(BTreeSet::new())
+        };
+
+        for entry in entries {
+            if let Some(name) = entry.strip_prefix('-') {
+                formats.0.remove(&name.parse()?);
+            } else if entry == "all" {
+                formats = Formats::default();
+            } else {
+                formats.0.insert(entry.trim_start_matches('+').parse()?);
+            }
+        }
+
+        if formats.0.is_empty() {
+            return Err(anyhow!("no formats left to sort"));
+        }
+        Ok(formats)
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder},
+    AnimationDecoder, DynamicImage, Frames,
+};
+use ratatui::layout::Rect;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Write},
+    path::Path,
+};
+use termion::cursor::Goto;
+
+use crate::format::Format;
+use crate::image_info::{self, Orientation};
+use crate::raw;
+use crate::video;
+
+// Command line tools of libheif, newer versions renamed heif-convert
+#[cfg(not(feature = "heif"))]
+const HEIF_DECODERS: [&str; 2] = ["heif-dec", "heif-convert"];
+
+// Cell size assumed when the terminal doesn't report its size in pixels
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// Size in pixels of a single terminal cell
+pub fn cell_size(terminal: Rect) -> (u32, u32) {
+    match termion::terminal_size_pixels() {
+        Ok((width, height)) if width > 0 && height > 0 && terminal.area() > 0 => (
+            (width as u32 / terminal.width as u32).max(1),
+            (height as u32 / terminal.height as u32).max(1),
+        ),
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+/// Size in pixels of an area of the terminal
+pub fn pixel_size(area: Rect, terminal: Rect) -> (u32, u32) {
+    let (fontw, fonth) = cell_size(terminal);
+    (area.width as u32 * fontw, area.height as u32 * fonth)
+}
+
+/// Scale (width, height) down to fit inside (max_width, max_height), keeping
+/// the aspect ratio. Images smaller than the box are left untouched.
+pub fn fit(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let (mut width, mut height) = (width.max(1), height.max(1));
+    if width > max_width {
+        // width _ height
+        // max_width _ max_width * height / width
+        height = max_width * height / width;
+        width = max_width;
+    }
+    if height > max_height {
+        // height _ width
+        // max_height _ max_height * width / height
+        width = max_height * width / height;
+        height = max_height;
+    }
+    (width.max(1), height.max(1))
+}
+
+/// Decode every frame of an image, turn them upright and downscale them so
+/// they fit inside (max_width, max_height). Still images have a single frame.
+pub fn load_frames(path: &Path, max_width: u32, max_height: u32) -> Result<Vec<DynamicImage>> {
+    let error = |e| anyhow!("could not decode {}: {}", path.display(), e);
+
+    // Frames of animations are downscaled as they are decoded, only one of
+    // them is ever held at full size
+    if let Some(frames) = decode_animation(path)? {
+        let orientation = image_info::orientation(path);
+        return frames
+            .map(|frame| {
+                let image = DynamicImage::ImageRgba8(frame.map_err(error)?.into_buffer());
+                Ok(downscale(image, orientation, max_width, max_height))
+            })
+            .collect();
+    }
+
+    let (image, orientation) = decode(path)?;
+    Ok(vec![downscale(image, orientation, max_width, max_height)])
+}
+
+// Fit an image inside (max_width, max_height) once it's upright
+fn downscale(
+    image: DynamicImage,
+    orientation: Orientation,
+    max_width: u32,
+    max_height: u32,
+) -> DynamicImage {
+    // Downscale before rotating, it's much cheaper
+    let (max_width, max_height) = if orientation.swaps_dimensions() {
+        (max_height, max_width)
+    } else {
+        (max_width, max_height)
+    };
+
+    let (width, height) = fit(image.width(), image.height(), max_width, max_height);
+    let image = if (width, height) == (image.width(), image.height()) {
+        image
+    } else {
+        image.thumbnail_exact(width, height)
+    };
+    orientation.apply(image)
+}
+
+/// Decode a whole image as it's stored, along with the way to turn it upright
+pub fn decode(path: &Path) -> Result<(DynamicImage, Orientation)> {
+    match Format::from_path(path) {
+        // libheif already turns them upright
+        Some(format) if format.needs_libheif() => Ok((decode_heif(path)?, Orientation::Normal)),
+        Some(Format::Raw) => {
+            let preview = raw::preview(path)?;
+            Ok((preview.decode()?, preview.orientation))
+        }
+        // ffmpeg turns them upright too
+        Some(Format::Video) => Ok((video::poster(path)?, Orientation::Normal)),
+        _ => {
+            let image = image::open(path)
+                .map_err(|e| anyhow!("could not decode {}: {}", path.display(), e))?;
+            Ok((image, image_info::orientation(path)))
+        }
+    }
+}
+
+// The frames of an animated GIF or APNG, decoded as they are iterated over,
+// None for anything else
+fn decode_animation(path: &Path) -> Result<Option<Frames<'static>>> {
+    let error = |e| anyhow!("could not decode {}: {}", path.display(), e);
+    let file = BufReader::new(File::open(path).map_err(|e| error(e.into()))?);
+
+    match Format::from_path(path) {
+        Some(Format::Gif) => Ok(Some(GifDecoder::new(file).map_err(error)?.into_frames())),
+        Some(Format::Png) => {
+            let decoder = PngDecoder::new(file).map_err(error)?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            Ok(Some(decoder.apng().into_frames()))
+        }
+        _ => Ok(None),
+    }
+}
+
+// There is no pure Rust decoder for HEIC and AVIF, libheif decodes them
+#[cfg(feature = "heif")]
+fn decode_heif(path: &Path) -> Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let error = |e: libheif_rs::HeifError| anyhow!("could not decode {}: {}", path.display(), e);
+    let bytes = fs::read(path)?;
+    let context = HeifContext::read_from_bytes(&bytes).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(error)?;
+
+    let (width, height) = (image.width(), image.height());
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| anyhow!("could not decode {}", path.display()))?;
+    // Rows may be padded past the width
+    let row = width as usize * 4;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+    let image = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("could not decode {}", path.display()))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+// Without libheif linked in, its command line tools convert them to a
+// temporary PNG instead
+#[cfg(not(feature = "heif"))]
+fn decode_heif(path: &Path) -> Result<DynamicImage> {
+    use std::{
+        env, process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use subprocess::{Exec, NullFile};
+
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let png = env::temp_dir().join(format!(
+        "image-sorter-{}-heif-{}.png",
+        process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    for decoder in HEIF_DECODERS {
+        let status = Exec::cmd(decoder)
+            .arg(path)
+            .arg(&png)
+            .stdout(NullFile)
+            .stderr(NullFile)
+            .join();
+        match status {
+            // Not installed under this name
+            Err(_) => continue,
+            Ok(status) if status.success() => {
+                let image = image::open(&png);
+                fs::remove_file(&png).ok();
+                return image.map_err(|e| anyhow!("could not decode {}: {}", path.display(), e));
+            }
+            Ok(_) => {
+                fs::remove_file(&png).ok();
+                return Err(anyhow!("{} could not decode {}", decoder, path.display()));
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "could not decode {}: install libheif (heif-dec) to display HEIC and AVIF images",
+        path.display()
+    ))
+}
+
+/// Images drawn inline (sixel, iTerm2) are part of the text grid, so the only
+/// way to remove them is to overwrite the cells underneath. The terminal
+/// backend doesn't know about them, hence it won't do it for us.
+pub fn clear_area(area: Rect) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    let blank = " ".repeat(area.width as usize);
+    for y in area.top()..area.bottom() {
+        write!(stdout, "{}{}", Goto(area.x + 1, y + 1), blank)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek},
+    path::Path,
+};
+
+use crate::bmff::{be_u16, be_u32, find_box, next_box, read_at};
+use crate::format::Format;
+use crate::graphics;
+use crate::raw;
+
+// Item property associations are a few bytes per item, even photos split in
+// tiles have little more than a hundred items
+const MAX_IPMA_SIZE: u64 = 1 << 20;
+
+/// What we know about an image without decoding it
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+}
+
+impl ImageInfo {
+    /// Dimensions of the image once it's displayed upright
+    pub fn display_dimensions(&self) -> (u32, u32) {
+        if self.orientation.swaps_dimensions() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+/// EXIF orientation, how the stored pixels must be transformed to display
+/// the image upright
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Orientation {
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    Transpose,
+    Rotate90,
+    Transverse,
+    Rotate270,
+}
+
+impl Orientation {
+    pub fn from_exif(value: u32) -> Self {
+        match value {
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => Orientation::Normal,
+        }
+    }
+
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Orientation::Transpose
+                | Orientation::Rotate90
+                | Orientation::Transverse
+                | Orientation::Rotate270
+        )
+    }
+
+    /// Transform a decoded image so it's upright
+    pub fn apply(self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Orientation::Normal => image,
+            Orientation::FlipHorizontal => image.fliph(),
+            Orientation::Rotate180 => image.rotate180(),
+            Orientation::FlipVertical => image.flipv(),
+            Orientation::Transpose => image.rotate90().fliph(),
+            Orientation::Rotate90 => image.rotate90(),
+            Orientation::Transverse => image.rotate270().fliph(),
+            Orientation::Rotate270 => image.rotate270(),
+        }
+    }
+}
+
+/// Read the dimensions and orientation of an image from its headers
+pub fn probe(path: &Path) -> Result<ImageInfo> {
+    match Format::from_path(path) {
+        // libheif turns them upright when decoding
+        Some(format) if format.needs_libheif() => {
+            if let Some((width, height)) = heif_dimensions(path) {
+                return Ok(ImageInfo {
+                    width,
+                    height,
+                    orientation: Orientation::Normal,
+                });
+            }
+        }
+        _ => {}
+    }
+
+    match Format::from_path(path) {
+        // Their headers can't be read without ffmpeg, or weren't understood
+        // above, decode them whole
+        Some(format) if format.needs_libheif() || format == Format::Video => {
+            let (image, orientation) = graphics::decode(path)?;
+            return Ok(ImageInfo {
+                width: image.width(),
+                height: image.height(),
+                orientation,
+            });
+        }
+        Some(Format::Raw) => {
+            let preview = raw::preview(path)?;
+            return Ok(ImageInfo {
+                width: preview.width,
+                height: preview.height,
+                orientation: preview.orientation,
+            });
+        }
+        _ => {}
+    }
+
+    let (width, height) = image::io::Reader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?
+        .into_dimensions()
+        .map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+
+    Ok(ImageInfo {
+        width,
+        height,
+        orientation: orientation(path),
+    })
+}
+
+/// Dimensions of the primary image of a HEIF or AVIF file once it's upright,
+/// from the properties associated with it: `ispe` has its size, and `irot`
+/// may turn it by a quarter
+fn heif_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut file = File::open(path).ok()?;
+    let end = file.metadata().ok()?.len();
+    let (meta, meta_end) = find_box(&mut file, 0, end, b"meta")?;
+    // A full box, its children come after the version and flags
+    let meta = meta + 4;
+
+    let (pitm, _) = find_box(&mut file, meta, meta_end, b"pitm")?;
+    let pitm = read_at(&mut file, pitm, 8)?;
+    let primary = match pitm[0] {
+        0 => be_u16(&pitm[4..]) as u32,
+        _ => be_u32(&pitm[4..]),
+    };
+
+    let (iprp, iprp_end) = find_box(&mut file, meta, meta_end, b"iprp")?;
+    let (ipma, ipma_end) = find_box(&mut file, iprp, iprp_end, b"ipma")?;
+    if ipma_end - ipma > MAX_IPMA_SIZE {
+        return None;
+    }
+    let ipma = read_at(&mut file, ipma, (ipma_end - ipma) as usize)?;
+    let properties = item_properties(&ipma, primary)?;
+
+    // Properties are numbered from 1, in the order they are in ipco
+    let (ipco, ipco_end) = find_box(&mut file, iprp, iprp_end, b"ipco")?;
+    let (mut offset, mut index) = (ipco, 0);
+    let (mut size, mut rotated) = (None, false);
+    while let Some((kind, content, box_end)) = next_box(&mut file, offset, ipco_end) {
+        offset = box_end;
+        index += 1;
+        if !properties.contains(&index) {
+            continue;
+        }
+        match &kind {
+            b"ispe" => {
+                let ispe = read_at(&mut file, content, 12)?;
+                size = Some((be_u32(&ispe[4..]), be_u32(&ispe[8..])));
+            }
+            b"irot" => {
+                let irot = read_at(&mut file, content, 1)?;
+                rotated = irot[0] & 1 == 1;
+            }
+            _ => {}
+        }
+    }
+
+    let (width, height) = size.filter(|&(width, height)| width > 0 && height > 0)?;
+    Some(if rotated {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+// Numbers of the properties associated with an item in an ipma box
+fn item_properties(ipma: &[u8], item: u32) -> Option<Vec<u32>> {
+    let (version, flags) = (*ipma.first()?, *ipma.get(3)?);
+    let count = be_u32(ipma.get(4..8)?);
+    let mut offset = 8;
+    for _ in 0..count {
+        let id = if version < 1 {
+            offset += 2;
+            be_u16(ipma.get(offset - 2..offset)?) as u32
+        } else {
+            offset += 4;
+            be_u32(ipma.get(offset - 4..offset)?)
+        };
+        let associations = *ipma.get(offset)? as usize;
+        offset += 1;
+
+        // The top bit tells whether the property is essential
+        let mut properties = vec![];
+        for _ in 0..associations {
+            let property = if flags & 1 == 1 {
+                offset += 2;
+                be_u16(ipma.get(offset - 2..offset)?) as u32 & 0x7fff
+            } else {
+                offset += 1;
+                *ipma.get(offset - 1)? as u32 & 0x7f
+            };
+            properties.push(property);
+        }
+        if id == item {
+            return Some(properties);
+        }
+    }
+    None
+}
+
+/// Orientation from the EXIF metadata, images without it are upright
+pub fn orientation(path: &Path) -> Orientation {
+    File::open(path).map_or(Orientation::Normal, |file| {
+        read_orientation(&mut BufReader::new(file))
+    })
+}
+
+/// Orientation from the EXIF metadata of an image being read
+pub fn read_orientation<R: BufRead + Seek>(reader: &mut R) -> Orientation {
+    exif::Reader::new()
+        .read_from_container(reader)
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .map_or(Orientation::Normal, Orientation::from_exif)
+}
+
+/// When the photo was taken as per its EXIF metadata, e.g.
+/// "2021-07-14 18:30:05", which sorts chronologically as is
+pub fn capture_date(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .iter()
+        .filter_map(|tag| exif.get_field(*tag, exif::In::PRIMARY))
+        .find_map(|field| match &field.value {
+            exif::Value::Ascii(values) => exif::DateTime::from_ascii(values.first()?).ok(),
+            _ => None,
+        })
+        .map(|date| date.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn boxed(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn heif(rotation: u8) -> Vec<u8> {
+        let ispe = boxed(b"ispe", &[0, 0, 0, 0, 0, 0, 2, 128, 0, 0, 1, 224]);
+        let irot = boxed(b"irot", &[rotation]);
+        // Item 1 has the size and rotation, item 2 only a size of its own
+        let thumb = boxed(b"ispe", &[0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 48]);
+        let ipco = boxed(b"ipco", &[thumb, ispe, irot].concat());
+        let ipma = boxed(
+            b"ipma",
+            &[0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 1, 0x01, 0, 1, 2, 0x82, 0x83],
+        );
+        let iprp = boxed(b"iprp", &[ipco, ipma].concat());
+        let pitm = boxed(b"pitm", &[0, 0, 0, 0, 0, 1]);
+        let meta = boxed(b"meta", &[&[0, 0, 0, 0][..], &pitm, &iprp].concat());
+        [boxed(b"ftyp", b"heic\0\0\0\0"), meta].concat()
+    }
+
+    fn dimensions(bytes: &[u8], name: &str) -> Option<(u32, u32)> {
+        let path = env::temp_dir().join(format!("image-sorter-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let dimensions = heif_dimensions(&path);
+        fs::remove_file(&path).unwrap();
+        dimensions
+    }
+
+    #[test]
+    fn heif_primary_image() {
+        assert_eq!(dimensions(&heif(0), "upright.heic"), Some((640, 480)));
+        assert_eq!(dimensions(&heif(2), "upside-down.heic"), Some((640, 480)));
+        assert_eq!(dimensions(&heif(3), "rotated.heic"), Some((480, 640)));
+    }
+
+    #[test]
+    fn heif_truncated() {
+        let bytes = heif(0);
+        assert_eq!(
+            dimensions(&bytes[..bytes.len() - 6], "truncated.heic"),
+            None
+        );
+        assert_eq!(dimensions(&bytes[..20], "header.heic"), None);
+    }
+}
@@ -1,16 +1,29 @@
 use termion::event::Key;
 
-use crate::app::{Action, App};
+use crate::app::{Action, App, Mode};
+use crate::view::Zoom;
 
 pub fn handle_key_main(key: Key, app: &mut App) {
     match key {
         Key::Backspace => {
             if let Some(i) = app.current_image() {
-                app.push_action(Action::Delete(i));
+                // Duplicates aren't all deleted, one copy is always kept
+                if !app.is_last_copy(&i) {
+                    app.push_action(Action::Delete(i));
+                }
             }
         }
         Key::Ctrl(key) => handle_app_key(key, app),
-        Key::Char(key) => handle_mapping_key(key, app),
+        Key::Char(key) if app.key_mapping.contains_key(&key) => handle_mapping_key(key, app, false),
+        // The uppercase of a bound key copies, unless it is bound itself
+        Key::Char(key) if app.key_mapping.contains_key(&key.to_ascii_lowercase()) => {
+            handle_mapping_key(key.to_ascii_lowercase(), app, true)
+        }
+        Key::Char(key) => handle_viewer_key(key, app),
+        Key::Up => app.view.pan(0, -1),
+        Key::Down => app.view.pan(0, 1),
+        Key::Left => app.view.pan(-1, 0),
+        Key::Right => app.view.pan(1, 0),
         _ => {}
     }
 }
@@ -23,21 +36,47 @@ fn handle_app_key(key: char, app: &mut App) {
             }
         }
         'z' => app.pop_action(),
+        'n' if app.discovery.truncated => app.next_batch(),
+        'k' => app.keep_duplicate(),
         _ => {}
     }
 }
 
-fn handle_mapping_key(key: char, app: &mut App) {
+fn handle_mapping_key(key: char, app: &mut App, copy: bool) {
     if let Some(mut path) = app.key_mapping.get_mut(&key).cloned() {
         if let Some(image_path) = app.current_image() {
-            if let Some(Action::Rename(name)) = app.actions.last() {
+            if let Some(name) = app.pending_rename() {
                 path.push(name);
             }
-            app.push_action(Action::Move(image_path, path));
+            let mode = if copy {
+                Mode::Copy
+            } else {
+                app.key_modes.get(&key).copied().unwrap_or_default()
+            };
+            app.push_action(mode.action(image_path, path));
         }
     }
 }
 
+// Bound keys take precedence, so hjkl only pan when they aren't mapped
+fn handle_viewer_key(key: char, app: &mut App) {
+    match key {
+        '+' => app.view.zoom_in(),
+        '-' => app.view.zoom_out(),
+        '=' => app.view.zoom_to(Zoom::Fit),
+        '1' => app.view.zoom_to(Zoom::Percent(100)),
+        '2' => app.view.zoom_to(Zoom::Percent(200)),
+        'k' => app.view.pan(0, -1),
+        'j' => app.view.pan(0, 1),
+        'h' => app.view.pan(-1, 0),
+        'l' => app.view.pan(1, 0),
+        ' ' => app.playback.toggle_pause(),
+        ',' => app.playback.step(-1),
+        '.' => app.playback.step(1),
+        _ => {}
+    }
+}
+
 pub fn handle_key_script(key: Key, app: &mut App) {
     match key {
         Key::Up | Key::Char('k') => app.scroll_up(),
@@ -82,15 +121,11 @@ pub fn handle_key_input(key: Key, app: &mut App) {
         Key::Home | Key::Ctrl('a') => {
             app.input_idx = 0;
         }
-        Key::Left | Key::Ctrl('b') => {
-            if app.input_idx > 0 {
-                app.input_idx -= 1;
-            }
+        Key::Left | Key::Ctrl('b') if app.input_idx > 0 => {
+            app.input_idx -= 1;
         }
-        Key::Right | Key::Ctrl('f') => {
-            if app.input_idx < app.input.len() {
-                app.input_idx += 1;
-            }
+        Key::Right | Key::Ctrl('f') if app.input_idx < app.input.len() => {
+            app.input_idx += 1;
         }
         Key::Esc => {
             app.enable_input = false;
@@ -100,16 +135,14 @@ pub fn handle_key_input(key: Key, app: &mut App) {
             app.push_action(Action::Rename(input_str));
             app.enable_input = false;
         }
-        Key::Backspace | Key::Ctrl('h') => {
-            if !app.input.is_empty() && app.input_idx > 0 {
-                app.input.remove(app.input_idx - 1);
-                app.input_idx -= 1;
-            }
+        Key::Backspace | Key::Ctrl('h') if !app.input.is_empty() && app.input_idx > 0 => {
+            app.input.remove(app.input_idx - 1);
+            app.input_idx -= 1;
         }
-        Key::Delete | Key::Ctrl('d') => {
-            if !app.input.is_empty() && app.input_idx < app.input.len() {
-                app.input.remove(app.input_idx);
-            }
+        Key::Delete | Key::Ctrl('d')
+            if !app.input.is_empty() && app.input_idx < app.input.len() =>
+        {
+            app.input.remove(app.input_idx);
         }
         Key::Char(c) => {
             app.input.insert(app.input_idx, c);
@@ -1,28 +1,54 @@
+mod animation;
 mod app;
+mod bmff;
+mod discovery;
+mod duplicates;
 mod event;
-mod image_display;
+mod filter;
+mod format;
+mod graphics;
+mod image_info;
 mod input;
+mod order;
+mod prefetch;
+mod raw;
 mod render;
+mod renderer;
+mod similar;
+mod video;
+mod view;
+mod watch;
 
 use anyhow::{anyhow, Result};
 use expanduser::expanduser;
+use globset::Glob;
 use ratatui::{backend::TermionBackend, Terminal};
 use std::{io, path::PathBuf, time::Duration};
 use structopt::StructOpt;
 use termion::{cursor::Goto, event::Key, raw::IntoRawMode, screen::IntoAlternateScreen};
 
-use crate::app::{App, TabId};
+use crate::app::{App, Mode, TabId};
+use crate::discovery::{Limit, Symlinks};
+use crate::duplicates::Existing;
 use crate::event::{Event, EventsListener};
-use crate::image_display::ImageDisplay;
+use crate::format::Formats;
 use crate::input::{handle_key_input, handle_key_main, handle_key_script};
+use crate::order::SortKey;
+use crate::prefetch::ImageLoader;
 use crate::render::{render_layout, render_main, render_script};
+use crate::renderer::{create_renderer, RendererKind, RendererOptions};
 
-fn parse_key_val(s: &str) -> Result<(char, PathBuf)> {
+fn parse_binding(s: &str) -> Result<(char, Mode, PathBuf)> {
     let pos = s
         .find('=')
         .ok_or_else(|| anyhow!(format!("invalid KEY=value: no `=` found in `{}`", s)))?;
     let dir: String = s[pos + 1..].parse()?;
-    Ok((s[..pos].parse()?, expanduser(dir)?))
+    // `:` can be bound too, the mode is after the last one
+    let (key, mode) = match s[..pos].rsplit_once(':') {
+        Some((key, mode)) if !key.is_empty() => (key, mode.parse()?),
+        _ => (&s[..pos], Mode::Move),
+    };
+    Ok((key.parse()?, mode, expanduser(dir)?))
 }
 
 #[derive(Debug, StructOpt)]
@@ -36,17 +62,31 @@ pub struct Opt {
     #[structopt(
         short,
         long,
-        help = "Bind a char to a folder, CHAR=FOLDER",
-        parse(try_from_str = parse_key_val),
+        help = "Bind a char to a folder, CHAR[:MODE]=FOLDER. MODE is how images get there: move (the default), copy, symlink, relative-symlink or hardlink",
+        parse(try_from_str = parse_binding),
     )]
-    bind: Vec<(char, PathBuf)>,
+    bind: Vec<(char, Mode, PathBuf)>,
 
     #[structopt(
-        help = "Images or folders containing images to sort",
+        help = "Images or folders containing images to sort, - reads a list of them from stdin",
         parse(from_os_str)
     )]
     input: Vec<PathBuf>,
 
+    #[structopt(
+        long,
+        help = "Read a list of images or folders from a file, one per line",
+        parse(from_os_str)
+    )]
+    files_from: Option<PathBuf>,
+
+    #[structopt(
+        short = "0",
+        long,
+        help = "Lists of images are separated by NUL instead of newlines, as with find -print0"
+    )]
+    null: bool,
+
     #[structopt(
         short,
         long,
@@ -54,6 +94,99 @@ pub struct Opt {
     )]
     recurse: bool,
 
+    #[structopt(
+        long,
+        help = "Symbolic links to follow in the input folders, each file is sorted once anyway",
+        default_value = "all",
+        possible_values = Symlinks::VARIANTS,
+    )]
+    follow_symlinks: Symlinks,
+
+    #[structopt(
+        short,
+        long,
+        help = "Keep watching the input folders, new images are added as they appear"
+    )]
+    watch: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Only sort images matching a glob, e.g. \"*.jpg\", can be repeated"
+    )]
+    include: Vec<Glob>,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help = "Skip images and folders matching a glob, e.g. \"*_thumb.jpg\", can be repeated"
+    )]
+    exclude: Vec<Glob>,
+
+    #[structopt(
+        long,
+        help = "Skip images and folders listed in a file, in gitignore syntax",
+        parse(from_os_str)
+    )]
+    ignore_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        allow_hyphen_values = true,
+        help = "Formats to sort, e.g. \"jpeg,png\", or \"-gif,-tiff\" to leave some out",
+        default_value = "all"
+    )]
+    formats: Formats,
+
+    #[structopt(
+        long,
+        help = "Sort RAW files along with the image of the same name, e.g. IMG_0001.CR2 with IMG_0001.JPG"
+    )]
+    group_sidecars: bool,
+
+    #[structopt(
+        long,
+        help = "Most images to sort at once, \"none\" for no limit. Ctrl-N loads the next batch",
+        default_value = "500"
+    )]
+    limit: Limit,
+
+    #[structopt(
+        long,
+        help = "Order to sort the images in",
+        default_value = "name",
+        possible_values = SortKey::VARIANTS,
+    )]
+    sort: SortKey,
+
+    #[structopt(long, help = "Reverse the order of the images")]
+    reverse: bool,
+
+    #[structopt(long, help = "Seed of the random order, to repeat a shuffle")]
+    seed: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Images with an identical copy of the same name in a bound folder are marked, or skipped",
+        default_value = "warn",
+        possible_values = Existing::VARIANTS,
+    )]
+    existing: Existing,
+
+    #[structopt(
+        long,
+        help = "Only sort images with identical copies, to pick which one to keep"
+    )]
+    duplicates: bool,
+
+    #[structopt(
+        long,
+        value_name = "DISTANCE",
+        conflicts_with = "duplicates",
+        help = "Put images that look alike next to each other, when their hashes differ by DISTANCE bits out of 64 at most, e.g. 10"
+    )]
+    similar: Option<u32>,
+
     #[structopt(
         short,
         long,
@@ -64,26 +197,62 @@ pub struct Opt {
 
     #[structopt(short, long, help = "App tick rate (ms)", default_value = "1000")]
     tick_rate: u64,
+
+    #[structopt(
+        long,
+        help = "Image renderer, auto picks the best one the terminal supports",
+        default_value = "auto",
+        possible_values = RendererKind::VARIANTS,
+    )]
+    renderer: RendererKind,
+
+    #[structopt(
+        long,
+        help = "Command of the external renderer, e.g. \"chafa -s {width}x{height} {path}\""
+    )]
+    renderer_command: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Number of upcoming images to decode in the background",
+        default_value = "3"
+    )]
+    prefetch_next: usize,
+
+    #[structopt(
+        long,
+        help = "Number of previous images to keep decoded, for undoing",
+        default_value = "1"
+    )]
+    prefetch_previous: usize,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let events_listener = EventsListener::new(Duration::from_millis(opt.tick_rate));
-    let mut app = App::new(opt)?;
+    let mut renderer = create_renderer(
+        opt.renderer,
+        RendererOptions {
+            command: opt.renderer_command.clone(),
+            loader: ImageLoader::new(opt.prefetch_next, opt.prefetch_previous),
+        },
+    )?;
+    let events_listener = EventsListener::new(Duration::from_millis(opt.tick_rate))?;
+    let mut app = App::new(opt, events_listener.sender())?;
 
     let stdout = io::stdout().into_raw_mode()?;
     let stdout = stdout.into_alternate_screen()?;
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let image_display = ImageDisplay::new()?;
-
     loop {
+        app.playback.load(app.current_image());
         terminal.draw(|f| {
             let window = render_layout(f, &app);
             if let Err(err) = match app.current_tab() {
-                TabId::Main => render_main(f, &app, &image_display, window),
-                TabId::Script => render_script(f, &app, window),
+                TabId::Main => render_main(f, &app, renderer.as_mut(), window),
+                TabId::Script => renderer
+                    .clear()
+                    .and_then(|_| render_script(f, &app, window)),
             } {
                 eprintln!("ERROR: {:?}", err);
                 panic!("{}", err);
@@ -98,8 +267,16 @@ fn main() -> Result<()> {
             terminal.hide_cursor()?;
         }
 
+        if let Some(at) = app.playback.next_frame_at() {
+            events_listener.wake_at(at);
+        }
+
         match events_listener.next()? {
-            Event::Tick => continue,
+            Event::Tick => app.playback.tick(),
+            Event::Discovered(images) => app.add_images(images),
+            Event::DiscoveryDone(truncated) => app.finish_batch(truncated),
+            Event::Duplicates(groups) => app.add_duplicates(groups),
+            Event::Clusters(clusters) => app.add_clusters(clusters),
             Event::Input(key) => {
                 if key == Key::Ctrl('c') {
                     break;
@@ -125,3 +302,22 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings() {
+        let (key, mode, _) = parse_binding("a=/a").unwrap();
+        assert_eq!((key, mode), ('a', Mode::Move));
+        let (key, mode, _) = parse_binding("a:copy=/a").unwrap();
+        assert_eq!((key, mode), ('a', Mode::Copy));
+        let (key, mode, _) = parse_binding(":=/a").unwrap();
+        assert_eq!((key, mode), (':', Mode::Move));
+        let (key, mode, _) = parse_binding("::hardlink=/a").unwrap();
+        assert_eq!((key, mode), (':', Mode::HardLink));
+        assert!(parse_binding("a:bogus=/a").is_err());
+        assert!(parse_binding("ab=/a").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Result};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::image_info;
+
+/// What images are ordered by
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SortKey {
+    Name,
+    Natural,
+    Mtime,
+    Size,
+    Exif,
+    Random,
+}
+
+impl SortKey {
+    pub const VARIANTS: &'static [&'static str] =
+        &["name", "natural", "mtime", "size", "exif", "random"];
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "natural" => Ok(SortKey::Natural),
+            "mtime" => Ok(SortKey::Mtime),
+            "size" => Ok(SortKey::Size),
+            "exif" => Ok(SortKey::Exif),
+            "random" => Ok(SortKey::Random),
+            _ => Err(anyhow!("unknown sort order `{}`", s)),
+        }
+    }
+}
+
+/// The order images are sorted in. Ties are broken by name, so the same
+/// folder always comes out the same way.
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub key: SortKey,
+    pub reverse: bool,
+    pub seed: u64,
+    // Capture dates read ahead while discovering images, so sorting a batch
+    // doesn't open every image again
+    dates: Arc<Mutex<HashMap<PathBuf, Option<String>>>>,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            key: SortKey::Name,
+            reverse: false,
+            seed: 0,
+            dates: Arc::default(),
+        }
+    }
+}
+
+impl Order {
+    /// A random seed is picked when none is given
+    pub fn new(key: SortKey, reverse: bool, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64)
+        });
+        Order {
+            key,
+            reverse,
+            seed,
+            ..Order::default()
+        }
+    }
+
+    /// Read what an image is sorted by ahead, when it takes opening it
+    pub fn prepare(&self, image: &Path) {
+        if self.key == SortKey::Exif {
+            let date = image_info::capture_date(image);
+            self.dates.lock().unwrap().insert(image.to_path_buf(), date);
+        }
+    }
+
+    pub fn sort(&self, images: &mut [PathBuf]) {
+        match self.key {
+            SortKey::Name => images.sort(),
+            SortKey::Natural => images.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b))),
+            // Images without a value, e.g. files that can't be read, go last
+            _ => images.sort_by_cached_key(|image| {
+                let value = self.value(image);
+                (value.is_none(), value, image.clone())
+            }),
+        }
+
+        if self.reverse {
+            images.reverse();
+        }
+    }
+
+    /// Compare two images the way `sort` orders them
+    pub fn cmp(&self, a: &Path, b: &Path) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Name => a.cmp(b),
+            SortKey::Natural => natural_cmp(a, b).then_with(|| a.cmp(b)),
+            _ => {
+                let (x, y) = (self.value(a), self.value(b));
+                (x.is_none(), x)
+                    .cmp(&(y.is_none(), y))
+                    .then_with(|| a.cmp(b))
+            }
+        };
+
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Insert an image in its place among sorted images, after the first
+    /// `start` ones which are left alone
+    pub fn insert(&self, images: &mut Vec<PathBuf>, start: usize, image: PathBuf) {
+        let start = start.min(images.len());
+        let index = start
+            + images[start..].partition_point(|other| self.cmp(other, &image) == Ordering::Less);
+        images.insert(index, image);
+    }
+
+    // What an image is ordered by before its name, for the keys other than
+    // the name itself
+    fn value(&self, image: &Path) -> Option<Value> {
+        match self.key {
+            SortKey::Name | SortKey::Natural => None,
+            SortKey::Mtime => image
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .map(Value::Time),
+            SortKey::Size => image.metadata().map(|m| Value::Size(m.len())).ok(),
+            SortKey::Exif => {
+                let prepared = self.dates.lock().unwrap().get(image).cloned();
+                prepared
+                    .unwrap_or_else(|| image_info::capture_date(image))
+                    .map(Value::Date)
+            }
+            // Hashing each path, rather than shuffling the list, gives the
+            // same order whatever order the images were discovered in
+            SortKey::Random => Some(Value::Hash(fnv1a(self.seed, image))),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Time(SystemTime),
+    Size(u64),
+    Date(String),
+    Hash(u64),
+}
+
+// Compare paths with runs of digits compared by their value, e.g. img2.jpg
+// comes before img10.jpg
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let (a, b) = (a.to_string_lossy(), b.to_string_lossy());
+    let (mut a, mut b) = (a.as_ref(), b.as_ref());
+
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, rest_a) = split_digits(a);
+                let (y, rest_b) = split_digits(b);
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = rest_a;
+                b = rest_b;
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+fn fnv1a(seed: u64, path: &Path) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in seed
+        .to_le_bytes()
+        .iter()
+        .chain(path.to_string_lossy().as_bytes())
+    {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut images = paths(&[
+            "img10.jpg",
+            "img2.jpg",
+            "img02.jpg",
+            "b/1.jpg",
+            "a10/1.jpg",
+            "a9/2.jpg",
+        ]);
+        Order::new(SortKey::Natural, false, None).sort(&mut images);
+        assert_eq!(
+            images,
+            paths(&[
+                "a9/2.jpg",
+                "a10/1.jpg",
+                "b/1.jpg",
+                "img02.jpg",
+                "img2.jpg",
+                "img10.jpg"
+            ])
+        );
+
+        assert_eq!(
+            natural_cmp(Path::new("x007"), Path::new("x7")),
+            Ordering::Equal
+        );
+        assert_eq!(natural_cmp(Path::new("x"), Path::new("x1")), Ordering::Less);
+        assert_eq!(
+            natural_cmp(Path::new("é2"), Path::new("é10")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn insert_in_place() {
+        for &key in &[SortKey::Natural, SortKey::Random] {
+            for &reverse in &[false, true] {
+                let order = Order::new(key, reverse, Some(7));
+                let all = paths(&["3.jpg", "1.jpg", "20.jpg", "4.jpg", "10.jpg"]);
+                let mut images = vec![];
+                for image in all.iter().cloned() {
+                    order.insert(&mut images, 0, image);
+                }
+                let mut sorted = all.clone();
+                order.sort(&mut sorted);
+                assert_eq!(images, sorted);
+            }
+        }
+
+        // The images before start stay put
+        let order = Order::new(SortKey::Name, false, None);
+        let mut images = paths(&["c.jpg", "d.jpg"]);
+        order.insert(&mut images, 1, PathBuf::from("a.jpg"));
+        assert_eq!(images, paths(&["c.jpg", "a.jpg", "d.jpg"]));
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use image::{imageops::FilterType, DynamicImage};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use crate::graphics::load_frames;
+use crate::view::{Crop, View, Zoom};
+
+// Memory decoded images may take, animations have a copy of the image per
+// frame. The image on screen is kept whatever its size.
+const MAX_CACHE_BYTES: usize = 512 << 20;
+
+// Images are decoded for a given path and target size
+type Key = (PathBuf, u32, u32);
+type Frames = Vec<Arc<DynamicImage>>;
+
+/// Decodes images, keeping the most recent ones around, and prefetches the
+/// ones around the current image in a background thread
+#[derive(Clone)]
+pub struct ImageLoader {
+    shared: Arc<Shared>,
+    tx: Sender<Vec<Key>>,
+    next: usize,
+    previous: usize,
+}
+
+struct Shared {
+    cache: Mutex<Cache>,
+    // Notified whenever an image finishes decoding
+    decoded: Condvar,
+}
+
+struct Cache {
+    capacity: usize,
+    // Least recently used first, with all the frames of each image
+    images: VecDeque<(Key, Frames)>,
+    bytes: usize,
+    // Last image loaded for display, prefetching must not evict it
+    shown: Option<Key>,
+    decoding: HashSet<Key>,
+    // Why images couldn't be decoded, whatever the size. Trying again on
+    // every redraw would run the external decoders over and over.
+    failed: HashMap<PathBuf, String>,
+}
+
+impl ImageLoader {
+    /// Prefetch up to `next` images after the current one and `previous`
+    /// images before it
+    pub fn new(next: usize, previous: usize) -> Self {
+        let shared = Arc::new(Shared {
+            cache: Mutex::new(Cache {
+                // The current image, plus one to spare while moving on
+                capacity: next + previous + 2,
+                images: VecDeque::new(),
+                bytes: 0,
+                shown: None,
+                decoding: HashSet::new(),
+                failed: HashMap::new(),
+            }),
+            decoded: Condvar::new(),
+        });
+        let (tx, rx) = unbounded::<Vec<Key>>();
+
+        let worker = shared.clone();
+        thread::spawn(move || prefetch_worker(worker, rx));
+
+        ImageLoader {
+            shared,
+            tx,
+            next,
+            previous,
+        }
+    }
+
+    /// Decode a frame of an image downscaled to fit inside (max_width,
+    /// max_height), or take it from the cache. Still images only have the
+    /// first frame, and animations are decoded whole.
+    pub fn load(
+        &self,
+        path: &Path,
+        frame: usize,
+        max_width: u32,
+        max_height: u32,
+    ) -> Result<Arc<DynamicImage>> {
+        let key = (path.to_path_buf(), max_width, max_height);
+        self.shared.cache.lock().unwrap().shown = Some(key.clone());
+        let frames = self.shared.decode(key)?;
+        frames
+            .get(frame.min(frames.len().saturating_sub(1)))
+            .cloned()
+            .ok_or_else(|| anyhow!("{} has no frames", path.display()))
+    }
+
+    /// Decode the part of an image that is visible with the given view, at
+    /// the size it's displayed in a pane of (max_width, max_height)
+    pub fn load_view(
+        &self,
+        path: &Path,
+        frame: usize,
+        view: View,
+        max_width: u32,
+        max_height: u32,
+    ) -> Result<(Arc<DynamicImage>, Crop)> {
+        if view.zoom == Zoom::Fit {
+            let image = self.load(path, frame, max_width, max_height)?;
+            let crop = Crop {
+                x: 0,
+                y: 0,
+                width: image.width(),
+                height: image.height(),
+            };
+            return Ok((image, crop));
+        }
+
+        let image = self.load(path, frame, u32::MAX, u32::MAX)?;
+        let (crop, (width, height)) =
+            view.layout(image.width(), image.height(), max_width, max_height);
+        let image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+        // Show actual pixels when zoomed in, checking sharpness is the point
+        let filter = if width > crop.width {
+            FilterType::Nearest
+        } else {
+            FilterType::Triangle
+        };
+        Ok((Arc::new(image.resize_exact(width, height, filter)), crop))
+    }
+
+    /// Start decoding the images around `current`, closest ones first.
+    /// Whatever was queued before and isn't decoded yet is dropped.
+    pub fn prefetch(&self, images: &[PathBuf], current: usize, max_width: u32, max_height: u32) {
+        self.prefetch_where(images, current, max_width, max_height, |_| true);
+    }
+
+    /// Same as `prefetch`, for the images around `current` that are
+    /// `decoded` by the renderer
+    pub fn prefetch_where<F>(
+        &self,
+        images: &[PathBuf],
+        current: usize,
+        max_width: u32,
+        max_height: u32,
+        decoded: F,
+    ) where
+        F: Fn(&Path) -> bool,
+    {
+        let next = images
+            .iter()
+            .skip(current + 1)
+            .filter(|path| decoded(path))
+            .take(self.next);
+        let previous = images[..current.min(images.len())]
+            .iter()
+            .rev()
+            .filter(|path| decoded(path))
+            .take(self.previous);
+
+        let queue = next
+            .chain(previous)
+            .map(|path| (path.clone(), max_width, max_height))
+            .collect();
+        self.tx.send(queue).ok();
+    }
+}
+
+impl Shared {
+    fn decode(&self, key: Key) -> Result<Frames> {
+        let mut cache = self.cache.lock().unwrap();
+        loop {
+            if let Some(image) = cache.get(&key) {
+                return Ok(image);
+            }
+            if let Some(error) = cache.failed.get(&key.0) {
+                return Err(anyhow!("{}", error));
+            }
+            if !cache.decoding.contains(&key) {
+                break;
+            }
+            // Someone else is decoding it already, wait for them
+            cache = self.decoded.wait(cache).unwrap();
+        }
+        cache.decoding.insert(key.clone());
+        drop(cache);
+
+        let result = load_frames(&key.0, key.1, key.2)
+            .map(|frames| frames.into_iter().map(Arc::new).collect::<Frames>());
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.decoding.remove(&key);
+        match &result {
+            Ok(image) => cache.insert(key, image.clone()),
+            Err(error) => {
+                cache.failed.insert(key.0, format!("{:#}", error));
+            }
+        }
+        self.decoded.notify_all();
+        result
+    }
+}
+
+impl Cache {
+    fn get(&mut self, key: &Key) -> Option<Frames> {
+        let index = self.images.iter().position(|(k, _)| k == key)?;
+        let entry = self.images.remove(index)?;
+        let image = entry.1.clone();
+        self.images.push_back(entry);
+        Some(image)
+    }
+
+    fn insert(&mut self, key: Key, image: Frames) {
+        self.bytes += size(&image);
+        self.images.push_back((key, image));
+        while self.images.len() > self.capacity || self.bytes > MAX_CACHE_BYTES {
+            let shown = &self.shown;
+            let evicted = match self
+                .images
+                .iter()
+                .position(|(k, _)| Some(k) != shown.as_ref())
+            {
+                Some(index) => self.images.remove(index),
+                None => break,
+            };
+            if let Some((_, frames)) = evicted {
+                self.bytes -= size(&frames);
+            }
+        }
+    }
+}
+
+fn size(frames: &Frames) -> usize {
+    frames.iter().map(|frame| frame.as_bytes().len()).sum()
+}
+
+fn prefetch_worker(shared: Arc<Shared>, rx: Receiver<Vec<Key>>) {
+    while let Ok(mut queue) = rx.recv() {
+        // Only the latest request matters, the user may have moved on
+        while let Ok(newer) = rx.try_recv() {
+            queue = newer;
+        }
+
+        for key in queue {
+            if !rx.is_empty() {
+                break;
+            }
+            // Errors show up when the image is displayed
+            shared.decode(key).ok();
+        }
+    }
+}
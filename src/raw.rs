@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageFormat};
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use crate::image_info::{self, Orientation};
+
+// Fujifilm RAF files start with this, followed by the offset and length of
+// the embedded JPEG at fixed positions
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+const RAF_JPEG_OFFSET: usize = 84;
+
+// TIFF tags of the IFDs where previews are found
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00fe;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+// Walking IFDs of a broken file could go on forever
+const MAX_IFDS: usize = 32;
+
+/// JPEG preview embedded in a camera RAW file. Cameras store one for their
+/// own screen, decoding the actual sensor data is out of our league.
+pub struct Preview {
+    jpeg: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+}
+
+impl Preview {
+    pub fn decode(&self) -> Result<DynamicImage> {
+        Ok(image::load_from_memory_with_format(
+            &self.jpeg,
+            ImageFormat::Jpeg,
+        )?)
+    }
+}
+
+/// Whether the file is a Fujifilm RAF, which isn't TIFF based like the rest
+pub fn is_raf(path: &Path) -> bool {
+    let mut magic = [0; RAF_MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == RAF_MAGIC
+}
+
+/// Find the largest JPEG preview of a RAW file
+pub fn preview(path: &Path) -> Result<Preview> {
+    let data = fs::read(path).map_err(|e| anyhow!("could not read {}: {}", path.display(), e))?;
+
+    let (candidates, orientation) = if data.starts_with(RAF_MAGIC) {
+        let offset = read_u32(&data, RAF_JPEG_OFFSET, false).unwrap_or(0) as usize;
+        let length = read_u32(&data, RAF_JPEG_OFFSET + 4, false).unwrap_or(0) as usize;
+        // The JPEG has its own EXIF, with the orientation
+        let orientation = data
+            .get(offset..offset + length)
+            .map_or(Orientation::Normal, |jpeg| {
+                image_info::read_orientation(&mut Cursor::new(jpeg))
+            });
+        (vec![(offset, length)], orientation)
+    } else {
+        tiff_previews(&data)
+    };
+
+    candidates
+        .into_iter()
+        .filter_map(|(offset, length)| {
+            let jpeg = data.get(offset..offset.checked_add(length)?)?;
+            let (width, height) = jpeg_dimensions(jpeg)?;
+            Some(Preview {
+                jpeg: jpeg.to_vec(),
+                width,
+                height,
+                orientation,
+            })
+        })
+        .max_by_key(|preview| preview.width as u64 * preview.height as u64)
+        .ok_or_else(|| anyhow!("no preview found in {}", path.display()))
+}
+
+// Offsets and lengths of the JPEGs referenced by any IFD, along with the
+// orientation of the main image
+fn tiff_previews(data: &[u8]) -> (Vec<(usize, usize)>, Orientation) {
+    let mut candidates = vec![];
+    let mut orientation = Orientation::Normal;
+
+    let little_endian = match data.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return (candidates, orientation),
+    };
+    let mut pending: Vec<usize> = read_u32(data, 4, little_endian)
+        .map(|offset| vec![offset as usize])
+        .unwrap_or_default();
+    let mut visited = 0;
+
+    while let Some(ifd) = pending.pop() {
+        visited += 1;
+        if ifd == 0 || visited > MAX_IFDS {
+            continue;
+        }
+        let entries = match read_u16(data, ifd, little_endian) {
+            Some(entries) => entries as usize,
+            None => continue,
+        };
+
+        let mut subfile_type = 0;
+        let mut compression = 0;
+        let mut strip = (0, 0);
+        let mut jpeg = (0, 0);
+        for i in 0..entries {
+            let entry = ifd + 2 + i * 12;
+            let (tag, value) = match read_entry(data, entry, little_endian) {
+                Some(field) => field,
+                None => break,
+            };
+            match tag {
+                TAG_NEW_SUBFILE_TYPE => subfile_type = value,
+                TAG_COMPRESSION => compression = value,
+                TAG_STRIP_OFFSETS => strip.0 = value as usize,
+                TAG_STRIP_BYTE_COUNTS => strip.1 = value as usize,
+                TAG_JPEG_OFFSET => jpeg.0 = value as usize,
+                TAG_JPEG_LENGTH => jpeg.1 = value as usize,
+                TAG_ORIENTATION if visited == 1 => orientation = Orientation::from_exif(value),
+                TAG_SUB_IFDS => pending.extend(sub_ifds(data, entry, little_endian)),
+                _ => {}
+            }
+        }
+
+        if jpeg.1 > 0 {
+            candidates.push(jpeg);
+        }
+        // Old style JPEG, or new style in a reduced resolution image. The
+        // full resolution one holds the sensor data as lossless JPEG.
+        if compression == 6 || (compression == 7 && subfile_type == 1) {
+            candidates.push(strip);
+        }
+
+        let next = ifd + 2 + entries * 12;
+        if let Some(next) = read_u32(data, next, little_endian) {
+            pending.push(next as usize);
+        }
+    }
+
+    (candidates, orientation)
+}
+
+// Tag and first value of an IFD entry
+fn read_entry(data: &[u8], entry: usize, little_endian: bool) -> Option<(u16, u32)> {
+    let tag = read_u16(data, entry, little_endian)?;
+    let kind = read_u16(data, entry + 2, little_endian)?;
+    let value = match kind {
+        // SHORT
+        3 => read_u16(data, entry + 8, little_endian)? as u32,
+        // LONG, IFD
+        4 | 13 => read_u32(data, entry + 8, little_endian)?,
+        _ => 0,
+    };
+    Some((tag, value))
+}
+
+// SubIFDs is a list of offsets, stored elsewhere when there is more than one
+fn sub_ifds(data: &[u8], entry: usize, little_endian: bool) -> Vec<usize> {
+    let count = read_u32(data, entry + 4, little_endian).unwrap_or(0) as usize;
+    let start = if count > 1 {
+        read_u32(data, entry + 8, little_endian).unwrap_or(0) as usize
+    } else {
+        entry + 8
+    };
+    (0..count.min(MAX_IFDS))
+        .filter_map(|i| read_u32(data, start + i * 4, little_endian))
+        .map(|offset| offset as usize)
+        .collect()
+}
+
+// Dimensions of a baseline or progressive JPEG, the only kinds the image
+// crate is able to display
+fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u32, u32)> {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+
+    let mut i = 2;
+    loop {
+        if *jpeg.get(i)? != 0xff {
+            return None;
+        }
+        let marker = *jpeg.get(i + 1)?;
+        match marker {
+            0xc0..=0xc2 => {
+                let height = read_u16(jpeg, i + 5, false)? as u32;
+                let width = read_u16(jpeg, i + 7, false)? as u32;
+                return Some((width, height));
+            }
+            // Lossless, hierarchical or arithmetic coded
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return None,
+            // Padding
+            0xff => i += 1,
+            _ => i += 2 + read_u16(jpeg, i + 2, false)? as usize,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if little_endian {
+        u16::from_le_bytes(bytes)
+    } else {
+        u16::from_be_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+
+    // An IFD with its entries, as (tag, kind, value), and the offset of the
+    // next one
+    fn ifd(entries: &[(u16, u16, u32)], next: u32, little_endian: bool) -> Vec<u8> {
+        let u16_bytes = |n: u16| {
+            if little_endian {
+                n.to_le_bytes()
+            } else {
+                n.to_be_bytes()
+            }
+        };
+        let u32_bytes = |n: u32| {
+            if little_endian {
+                n.to_le_bytes()
+            } else {
+                n.to_be_bytes()
+            }
+        };
+
+        let mut bytes = u16_bytes(entries.len() as u16).to_vec();
+        for &(tag, kind, value) in entries {
+            bytes.extend_from_slice(&u16_bytes(tag));
+            bytes.extend_from_slice(&u16_bytes(kind));
+            bytes.extend_from_slice(&u32_bytes(1));
+            match kind {
+                SHORT => bytes.extend_from_slice(&[&u16_bytes(value as u16)[..], &[0, 0]].concat()),
+                _ => bytes.extend_from_slice(&u32_bytes(value)),
+            }
+        }
+        bytes.extend_from_slice(&u32_bytes(next));
+        bytes
+    }
+
+    #[test]
+    fn previews_in_ifds() {
+        // IFD0 at 8, its SubIFD at 38 and IFD1 at 80
+        let data = [
+            &b"II*\0\x08\0\0\0"[..],
+            &ifd(
+                &[(TAG_ORIENTATION, SHORT, 6), (TAG_SUB_IFDS, LONG, 38)],
+                80,
+                true,
+            ),
+            &ifd(
+                &[
+                    (TAG_COMPRESSION, SHORT, 6),
+                    (TAG_STRIP_OFFSETS, LONG, 1000),
+                    (TAG_STRIP_BYTE_COUNTS, LONG, 2000),
+                ],
+                0,
+                true,
+            ),
+            &ifd(
+                &[(TAG_JPEG_OFFSET, LONG, 3000), (TAG_JPEG_LENGTH, LONG, 4000)],
+                0,
+                true,
+            ),
+        ]
+        .concat();
+        let (candidates, orientation) = tiff_previews(&data);
+        assert_eq!(candidates, vec![(3000, 4000), (1000, 2000)]);
+        assert_eq!(orientation, Orientation::from_exif(6));
+
+        // The reduced resolution image of a big endian file, the full one
+        // is the sensor data
+        let data = [
+            &b"MM\0*\0\0\0\x08"[..],
+            &ifd(
+                &[
+                    (TAG_NEW_SUBFILE_TYPE, LONG, 0),
+                    (TAG_COMPRESSION, SHORT, 7),
+                    (TAG_SUB_IFDS, LONG, 50),
+                ],
+                0,
+                false,
+            ),
+            &ifd(
+                &[
+                    (TAG_NEW_SUBFILE_TYPE, LONG, 1),
+                    (TAG_COMPRESSION, SHORT, 7),
+                    (TAG_STRIP_OFFSETS, LONG, 500),
+                    (TAG_STRIP_BYTE_COUNTS, LONG, 600),
+                ],
+                0,
+                false,
+            ),
+        ]
+        .concat();
+        assert_eq!(tiff_previews(&data).0, vec![(500, 600)]);
+    }
+
+    #[test]
+    fn broken_ifds() {
+        assert!(tiff_previews(b"").0.is_empty());
+        assert!(tiff_previews(b"II*\0\xff\xff\0\0").0.is_empty());
+
+        // IFDs leading back to themselves
+        let data = [
+            &b"II*\0\x08\0\0\0"[..],
+            &ifd(
+                &[(TAG_JPEG_OFFSET, LONG, 1), (TAG_JPEG_LENGTH, LONG, 2)],
+                8,
+                true,
+            ),
+        ]
+        .concat();
+        assert_eq!(tiff_previews(&data).0.len(), MAX_IFDS);
+
+        // Cut in the middle of an entry
+        assert!(tiff_previews(&data[..20]).0.is_empty());
+    }
+
+    #[test]
+    fn jpeg_sizes() {
+        let sof = |marker: u8| [0xff, marker, 0, 17, 8, 0x01, 0xe0, 0x02, 0x80];
+        let app0 = [0xff, 0xe0, 0, 4, 0, 0];
+        let jpeg = |marker| [&[0xff, 0xd8][..], &app0, &[0xff], &sof(marker)].concat();
+
+        assert_eq!(jpeg_dimensions(&jpeg(0xc0)), Some((640, 480)));
+        assert_eq!(jpeg_dimensions(&jpeg(0xc2)), Some((640, 480)));
+        // Lossless
+        assert_eq!(jpeg_dimensions(&jpeg(0xc3)), None);
+
+        let full = jpeg(0xc0);
+        assert_eq!(jpeg_dimensions(&full[..full.len() - 1]), None);
+        assert_eq!(jpeg_dimensions(&full[1..]), None);
+        // A segment length past the end
+        assert_eq!(jpeg_dimensions(&[0xff, 0xd8, 0xff, 0xe1, 0xff, 0xff]), None);
+    }
+}
@@ -4,14 +4,15 @@ use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Style},
     terminal::Frame,
-    text::{Line, Text},
-    widgets::{Block, Borders, Paragraph, Row, Table, Tabs},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Paragraph, Row, Table, Tabs, Wrap},
 };
 use std::{env, path, time::Duration};
 use tico::tico;
 
 use crate::app::{Action, App};
-use crate::image_display::ImageDisplay;
+use crate::renderer::{render_image, ImageRenderer};
+use crate::view::Zoom;
 
 pub fn render_layout<B>(f: &mut Frame<B>, app: &App) -> Rect
 where
@@ -37,25 +38,12 @@ where
 pub fn render_main<B>(
     f: &mut Frame<B>,
     app: &App,
-    image_display: &ImageDisplay,
+    renderer: &mut dyn ImageRenderer,
     window: Rect,
 ) -> Result<()>
 where
     B: Backend,
 {
-    let image_title = match app.current_image() {
-        None => "No more images left to sort".to_string(),
-        Some(image_path) => {
-            let image_path = image_path.display().to_string();
-            if let Some(Action::Rename(name)) = app.actions.last() {
-                format!("{} - Renamed to {}", image_path, name)
-            } else {
-                image_path
-            }
-        }
-    };
-    let image_block = Block::default().borders(Borders::ALL).title(image_title);
-
     let window_layout = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Min(10), Constraint::Length(30)].as_ref())
@@ -78,7 +66,7 @@ where
             [
                 Constraint::Length(3),
                 Constraint::Min(5),
-                Constraint::Length(11),
+                Constraint::Length(19),
             ]
             .as_ref(),
         )
@@ -88,12 +76,106 @@ where
     render_key_mapping(f, app, sidebar_layout[1]);
     render_controls(f, sidebar_layout[2]);
 
+    let image_container = Block::default().borders(Borders::ALL).inner(main_layout[0]);
+    let mut crop = None;
     if let Some(image_path) = app.current_image() {
-        let terminal_size = f.size();
-        let image_container = image_block.inner(main_layout[0]);
-        image_display.render_image(image_path, image_container, terminal_size)?;
+        let frame = app.playback.frame;
+        match render_image(f, renderer, image_path, frame, app.view, image_container) {
+            Ok(visible) => crop = visible,
+            // Clips are sorted even if no frame can be displayed
+            Err(err) if app.playback.clip.is_some() => {
+                renderer.clear()?;
+                render_clip_card(f, app, &err.to_string(), image_container);
+            }
+            Err(err) => {
+                // A broken image shouldn't take the whole program down
+                renderer.clear()?;
+                let error = Paragraph::new(Text::from(format!("Could not display image: {}", err)))
+                    .style(Style::default().fg(Color::Red))
+                    .wrap(Wrap { trim: true });
+                f.render_widget(error, image_container);
+            }
+        }
+        renderer.prefetch(&app.images, app.current, image_container, f.size());
+    } else {
+        renderer.clear()?;
     }
 
+    let image_title = match app.current_image() {
+        None if app.discovery.running => "Looking for images...".to_string(),
+        None if app.duplicates.hashing => "Looking for duplicates...".to_string(),
+        None => "No more images left to sort".to_string(),
+        Some(image_path) => {
+            let mut title = image_path.display().to_string();
+            let sidecars: Vec<String> = app
+                .sidecars(&image_path)
+                .iter()
+                .filter_map(|sidecar| sidecar.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect();
+            if !sidecars.is_empty() {
+                title = format!("{} (+ {})", title, sidecars.join(", "));
+            }
+            if let Some(name) = app.pending_rename() {
+                title = format!("{} - Renamed to {}", title, name);
+            }
+            let copies: Vec<String> = app
+                .copies()
+                .iter()
+                .map(|folder| folder.display().to_string())
+                .collect();
+            if !copies.is_empty() {
+                title = format!("{} - Also in {}", title, copies.join(", "));
+            }
+            if let Some(group) = app.duplicates.group(&image_path) {
+                let copy = group.iter().position(|copy| *copy == image_path);
+                title = format!("{} - Copy {}/{}", title, copy.unwrap_or(0) + 1, group.len());
+            }
+            if let Some(cluster) = app.similar.cluster(&image_path) {
+                let position = cluster.iter().position(|other| *other == image_path);
+                title = format!(
+                    "{} - Similar {}/{}",
+                    title,
+                    position.unwrap_or(0) + 1,
+                    cluster.len()
+                );
+            }
+            if let Some(clip) = app.playback.clip {
+                title = format!("{} - Video {}", title, clip.describe());
+            }
+            if app.playback.frame_count() > 1 {
+                title = format!(
+                    "{} - Frame {}/{}{}",
+                    title,
+                    app.playback.frame + 1,
+                    app.playback.frame_count(),
+                    if app.playback.paused { " (paused)" } else { "" }
+                );
+            }
+            if let (Zoom::Percent(percent), Some(crop)) = (app.view.zoom, crop) {
+                title = format!(
+                    "{} - {}% {}x{}+{}+{}",
+                    title, percent, crop.width, crop.height, crop.x, crop.y
+                );
+            }
+            title
+        }
+    };
+    let mut image_title = vec![Span::raw(image_title)];
+    if let Some(copy) = app.current_image().and_then(|i| app.already_sorted.get(&i)) {
+        let folder = copy.parent().unwrap_or(copy);
+        image_title.push(Span::raw(" "));
+        image_title.push(Span::styled(
+            format!(
+                " Already in {} ",
+                shorten_path(folder, home_dir().as_deref())
+            ),
+            Style::default().fg(Color::Black).bg(Color::Yellow),
+        ));
+    }
+    let image_block = Block::default()
+        .borders(Borders::ALL)
+        .title(Line::from(image_title));
     f.render_widget(image_block, main_layout[0]);
     if app.enable_input {
         render_rename_input(f, app, main_layout[1]);
@@ -102,6 +184,28 @@ where
     Ok(())
 }
 
+fn render_clip_card<B>(f: &mut Frame<B>, app: &App, reason: &str, window: Rect)
+where
+    B: Backend,
+{
+    let clip = app.playback.clip.unwrap_or_default();
+    let mut lines = vec![Line::from("Video clip")];
+    let description = clip.describe();
+    if !description.is_empty() {
+        lines.push(Line::from(description));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::styled(
+        reason.to_string(),
+        Style::default().fg(Color::DarkGray),
+    ));
+
+    let card = Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
+    f.render_widget(card, window);
+}
+
 fn render_rename_input<B>(f: &mut Frame<B>, app: &App, window: Rect)
 where
     B: Backend,
@@ -125,6 +229,13 @@ where
 {
     let status_block = Block::default().borders(Borders::ALL).title("Status");
     let mut status = format!("Sorted: {}/{}", app.current, app.images.len());
+    if app.discovery.running {
+        status.push_str(" (scanning)");
+    } else if app.duplicates.hashing || app.similar.clustering {
+        status.push_str(" (comparing)");
+    } else if app.discovery.truncated {
+        status.push_str(" (limited)");
+    }
     if let Some(last_save) = app.last_save {
         if last_save.elapsed() < STATUS_DURATION {
             status = "Script saved!".to_string();
@@ -151,21 +262,26 @@ fn shorten_path(path: &path::Path, home_dir: Option<&str>) -> String {
     tico(path, home_dir)
 }
 
+fn home_dir() -> Option<String> {
+    // This is not working on Windows according to https://doc.rust-lang.org/std/env/fn.home_dir.html
+    // There are probably more non-Windows dependencies, like w3m-img
+    // Replace with a cross-platform solution, if needed.
+    #[allow(deprecated)]
+    env::home_dir().map(|p| p.display().to_string())
+}
+
 fn render_key_mapping<B>(f: &mut Frame<B>, app: &App, window: Rect)
 where
     B: Backend,
 {
     let key_mapping_block = Block::default().borders(Borders::ALL).title("Key mapping");
-    // This is not working on Windows according to https://doc.rust-lang.org/std/env/fn.home_dir.html
-    // There are probably more non-Windows dependencies, like w3m-img
-    // Replace with a cross-platform solution, if needed.
-    #[allow(deprecated)]
-    let home_dir = env::home_dir().map(|p| p.display().to_string());
+    let home_dir = home_dir();
     let keys = app.key_mapping.iter().map(|(key, path)| {
-        Row::new(vec![
-            key.to_string(),
-            shorten_path(path, home_dir.as_deref()),
-        ])
+        let mut path = shorten_path(path, home_dir.as_deref());
+        if let Some(mode) = app.key_modes.get(key) {
+            path = format!("{} ({})", path, mode.name());
+        }
+        Row::new(vec![key.to_string(), path])
     });
 
     let key_mapping = Table::new(keys)
@@ -187,9 +303,17 @@ where
         Row::new(["", ""]),
         Row::new(["Ctrl-R", "Rename image"]),
         Row::new(["Ctrl-S", "Skip image"]),
+        Row::new(["Shift-key", "Copy image"]),
         Row::new(["Backspace", "Delete image"]),
         Row::new(["Ctrl-Z", "Undo action"]),
         Row::new(["Ctrl-W", "Save script"]),
+        Row::new(["Ctrl-N", "Load more images"]),
+        Row::new(["Ctrl-K", "Keep, delete copies"]),
+        Row::new(["", ""]),
+        Row::new(["+ / -", "Zoom in / out"]),
+        Row::new(["= / 1 / 2", "Fit / 100% / 200%"]),
+        Row::new(["Arrows", "Pan zoomed image"]),
+        Row::new(["Space , .", "Pause / step frame"]),
     ])
     .widths([Constraint::Length(10), Constraint::Length(20)].as_ref())
     .header(Row::new(["Key", "Action"]).style(Style::default().fg(Color::Red)))
@@ -224,20 +348,10 @@ where
                 format!("# Skipped {}", image.display()),
                 comment_style,
             )),
-            Action::MkDir(path) => {
-                lines.push(Line::from(format!("mkdir -p \"{}\"", path.display())))
-            }
-            Action::Move(image, path) => lines.push(Line::from(format!(
-                "mv \"{}\" \"{}\"",
-                image.display(),
-                path.display()
-            ))),
-            Action::Delete(image) => lines.push(Line::from(format!("rm \"{}\"", image.display()))),
-            _ => {}
+            _ => lines.extend(app.commands(action).into_iter().map(Line::from)),
         }
     }
 
-    let lines: Vec<Line> = lines.into_iter().map(Line::from).collect();
     let script_block = Block::default().borders(Borders::ALL);
     let paragraph = Paragraph::new(lines)
         .block(script_block)
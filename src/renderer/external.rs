@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use ratatui::{buffer::Buffer, layout::Rect};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use subprocess::{Exec, Redirection};
+use termion::cursor::Goto;
+
+use super::{ImageRenderer, Placement};
+use crate::graphics::clear_area;
+use crate::view::Crop;
+
+/// Renders images by running a user provided command, e.g. `chafa` or
+/// `timg`, and printing its output inside the image block.
+///
+/// The command is split on whitespace and the following placeholders are
+/// replaced in each argument: `{path}`, `{x}`, `{y}`, `{width}` and
+/// `{height}`, the last four being the image block in cells.
+pub struct ExternalDisplay {
+    command: Vec<String>,
+    last: Option<(PathBuf, Rect, Rect)>,
+}
+
+impl ExternalDisplay {
+    pub fn new(command: &str) -> Result<Self> {
+        let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+        if command.is_empty() {
+            return Err(anyhow!("the renderer command is empty"));
+        }
+
+        Ok(ExternalDisplay {
+            command,
+            last: None,
+        })
+    }
+
+    fn args(&self, image_path: &Path, block: Rect) -> Vec<String> {
+        self.command
+            .iter()
+            .map(|arg| {
+                arg.replace("{path}", &image_path.display().to_string())
+                    .replace("{x}", &block.x.to_string())
+                    .replace("{y}", &block.y.to_string())
+                    .replace("{width}", &block.width.to_string())
+                    .replace("{height}", &block.height.to_string())
+            })
+            .collect()
+    }
+}
+
+impl ImageRenderer for ExternalDisplay {
+    /// External commands can't zoom, the view is ignored
+    fn render_image(&mut self, placement: &Placement, _buf: &mut Buffer) -> Result<Option<Crop>> {
+        let Placement {
+            image_path,
+            block,
+            terminal,
+            ..
+        } = placement.clone();
+        let placement = (image_path, block, terminal);
+        if self.last.as_ref() == Some(&placement) {
+            return Ok(None);
+        }
+
+        self.clear()?;
+        let (image_path, block, terminal) = placement;
+        let args = self.args(&image_path, block);
+        let output = Exec::cmd(&args[0])
+            .args(&args[1..])
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()?;
+        if !output.success() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                args[0],
+                output.stderr_str().trim()
+            ));
+        }
+
+        // Commands print line by line from the cursor, lay each line at the
+        // start of the block so they don't spill over the rest of the UI
+        let mut stdout = io::stdout().lock();
+        let lines = output.stdout.split(|&b| b == b'\n');
+        for (y, line) in (block.top()..block.bottom()).zip(lines) {
+            write!(stdout, "{}", Goto(block.x + 1, y + 1))?;
+            stdout.write_all(line.strip_suffix(b"\r").unwrap_or(line))?;
+        }
+        write!(stdout, "{}", termion::style::Reset)?;
+        stdout.flush()?;
+
+        self.last = Some((image_path, block, terminal));
+        Ok(None)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        if let Some((_, block, _)) = self.last.take() {
+            clear_area(block)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use ratatui::{buffer::Buffer, layout::Rect, style::Color};
+use std::env;
+
+use super::{ImageRenderer, Placement};
+use crate::graphics::{cell_size, pixel_size};
+use crate::prefetch::ImageLoader;
+use crate::view::Crop;
+
+const UPPER_HALF_BLOCK: &str = "\u{2580}";
+
+/// Renders images with text only, using the upper half block character.
+/// Each cell displays two pixels: the foreground color paints the top half
+/// and the background color paints the bottom one.
+pub struct HalfBlockDisplay {
+    loader: ImageLoader,
+    truecolor: bool,
+    // Cells computed for the last image and area, decoding is expensive
+    last: Option<(Placement, Crop, HalfBlocks)>,
+}
+
+impl HalfBlockDisplay {
+    pub fn new(loader: ImageLoader) -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        HalfBlockDisplay {
+            loader,
+            truecolor: matches!(colorterm.as_str(), "truecolor" | "24bit"),
+            last: None,
+        }
+    }
+
+    fn half_blocks(&self, placement: &Placement) -> Result<(HalfBlocks, Crop)> {
+        let Placement {
+            image_path,
+            frame,
+            view,
+            block,
+            terminal,
+        } = placement;
+        let (fontw, fonth) = cell_size(*terminal);
+        let (max_width, max_height) = pixel_size(*block, *terminal);
+
+        // Fit the image in pixels first, so the aspect ratio of the font is
+        // honored, and then sample it down to two pixels per cell
+        let (image, crop) = self
+            .loader
+            .load_view(image_path, *frame, *view, max_width, max_height)?;
+        let columns = (image.width() / fontw).clamp(1, block.width as u32);
+        let rows = (image.height() * 2 / fonth).clamp(1, block.height as u32 * 2);
+        let image = image.thumbnail_exact(columns, rows).into_rgb8();
+
+        let mut cells = vec![];
+        for y in (0..rows).step_by(2) {
+            for x in 0..columns {
+                let top = image.get_pixel(x, y).0;
+                let bottom = if y + 1 < rows {
+                    Some(self.color(image.get_pixel(x, y + 1).0))
+                } else {
+                    None
+                };
+                cells.push((self.color(top), bottom));
+            }
+        }
+
+        let cells = HalfBlocks {
+            width: columns as u16,
+            cells,
+        };
+        Ok((cells, crop))
+    }
+
+    fn color(&self, [r, g, b]: [u8; 3]) -> Color {
+        if self.truecolor {
+            Color::Rgb(r, g, b)
+        } else {
+            Color::Indexed(ansi256(r, g, b))
+        }
+    }
+}
+
+impl ImageRenderer for HalfBlockDisplay {
+    fn render_image(&mut self, placement: &Placement, buf: &mut Buffer) -> Result<Option<Crop>> {
+        // Nothing fits in a terminal too small for the image block
+        if placement.block.area() == 0 {
+            return Ok(None);
+        }
+
+        let cached = matches!(&self.last, Some((last, _, _)) if last == placement);
+        if !cached {
+            let (cells, crop) = self.half_blocks(placement)?;
+            self.last = Some((placement.clone(), crop, cells));
+        }
+
+        match &self.last {
+            Some((_, crop, cells)) => {
+                cells.render(placement.block, buf);
+                Ok(Some(*crop))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn loader(&self) -> Option<&ImageLoader> {
+        Some(&self.loader)
+    }
+}
+
+/// Approximate an RGB color in the xterm 256 color palette
+fn ansi256(r: u8, g: u8, b: u8) -> u8 {
+    // Grays map better to the 24 step grayscale ramp than to the cube
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            _ => 232 + ((r as u16 - 8) * 24 / 241) as u8,
+        };
+    }
+
+    let level = |c: u8| -> u8 {
+        if c < 48 {
+            0
+        } else if c < 115 {
+            1
+        } else {
+            (c - 35) / 40
+        }
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// Cells of an image, see `HalfBlockDisplay`
+struct HalfBlocks {
+    width: u16,
+    // Top and bottom colors, the last row may lack the bottom half
+    cells: Vec<(Color, Option<Color>)>,
+}
+
+impl HalfBlocks {
+    fn render(&self, area: Rect, buf: &mut Buffer) {
+        let width = self.width as usize;
+        for (i, &(top, bottom)) in self.cells.iter().enumerate() {
+            let x = area.x as usize + i % width;
+            let y = area.y as usize + i / width;
+            if x >= area.right() as usize || y >= area.bottom() as usize {
+                continue;
+            }
+
+            let cell = buf
+                .get_mut(x as u16, y as u16)
+                .set_symbol(UPPER_HALF_BLOCK)
+                .set_fg(top);
+            if let Some(bottom) = bottom {
+                cell.set_bg(bottom);
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::ImageOutputFormat;
+use std::{
+    env,
+    io::{Cursor, Write},
+};
+use termion::cursor::Goto;
+
+use super::{Placement, Protocol};
+use crate::graphics::{cell_size, pixel_size};
+use crate::prefetch::ImageLoader;
+use crate::view::Crop;
+
+/// The iTerm2 inline images protocol
+/// (https://iterm2.com/documentation-images.html).
+pub struct Iterm;
+
+impl Iterm {
+    /// Whether the running terminal is known to support inline images
+    pub fn is_supported() -> bool {
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        let lc_terminal = env::var("LC_TERMINAL").unwrap_or_default();
+        term_program == "iTerm.app" || lc_terminal == "iTerm2"
+    }
+}
+
+impl Protocol for Iterm {
+    fn input(&mut self, loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+        iterm_input(loader, placement)
+    }
+}
+
+fn iterm_input(loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+    let Placement {
+        image_path,
+        frame,
+        view,
+        block,
+        terminal,
+    } = placement;
+    let (fontw, fonth) = cell_size(*terminal);
+    let (max_width, max_height) = pixel_size(*block, *terminal);
+
+    // Send a downscaled PNG rather than the original file, which may be huge
+    // or in a format the terminal can't decode
+    let (image, crop) = loader.load_view(image_path, *frame, *view, max_width, max_height)?;
+    let columns = image.width().div_ceil(fontw).max(1);
+    let rows = image.height().div_ceil(fonth).max(1);
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageOutputFormat::Png)?;
+    let png = png.into_inner();
+
+    let mut input = format!("{}", Goto(block.x + 1, block.y + 1)).into_bytes();
+    write!(
+        input,
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        columns,
+        rows,
+        STANDARD.encode(&png)
+    )?;
+    Ok((input, crop))
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+    ColorType, ImageEncoder,
+};
+use std::{
+    env,
+    io::{self, Write},
+};
+use termion::cursor::Goto;
+
+use super::{Capabilities, Placement, Protocol};
+use crate::graphics::{cell_size, pixel_size};
+use crate::prefetch::ImageLoader;
+use crate::view::Crop;
+
+// The protocol limits the payload of a single escape code to 4096 bytes
+const CHUNK_SIZE: usize = 4096;
+// Every image we transmit reuses the same id, so deleting it is trivial
+const IMAGE_ID: u32 = 1;
+
+/// The kitty terminal graphics protocol
+/// (https://sw.kovidgoyal.net/kitty/graphics-protocol/).
+pub struct Kitty;
+
+impl Kitty {
+    /// Whether the running terminal speaks the kitty graphics protocol. tmux
+    /// doesn't pass it through, whatever the terminal it runs in.
+    pub fn is_supported(terminal: Capabilities) -> bool {
+        if env::var_os("TMUX").is_some() {
+            return false;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        terminal.kitty
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || env::var_os("KITTY_WINDOW_ID").is_some()
+            || env::var_os("GHOSTTY_RESOURCES_DIR").is_some()
+            || matches!(term_program.as_str(), "WezTerm" | "ghostty")
+    }
+}
+
+impl Protocol for Kitty {
+    fn input(&mut self, loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+        kitty_input(loader, placement)
+    }
+
+    fn erase(&mut self, _placement: &Placement) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", IMAGE_ID)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+fn kitty_input(loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+    let Placement {
+        image_path,
+        frame,
+        view,
+        block,
+        terminal,
+    } = placement;
+    let (fontw, fonth) = cell_size(*terminal);
+    let (max_width, max_height) = pixel_size(*block, *terminal);
+
+    let (image, crop) = loader.load_view(image_path, *frame, *view, max_width, max_height)?;
+    let image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+    let columns = width.div_ceil(fontw).max(1);
+    let rows = height.div_ceil(fonth).max(1);
+
+    // PNG takes a fraction of the raw pixels to send, which adds up when
+    // playing animations. Fast compression is still much cheaper than
+    // sending more.
+    let mut png = vec![];
+    PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::Sub).write_image(
+        image.as_raw(),
+        width,
+        height,
+        ColorType::Rgba8,
+    )?;
+    let payload = STANDARD.encode(png);
+    let mut input = format!("{}", Goto(block.x + 1, block.y + 1)).into_bytes();
+    let chunks = payload.as_bytes().chunks(CHUNK_SIZE);
+    let last = chunks.len() - 1;
+
+    for (i, chunk) in chunks.enumerate() {
+        let more = if i == last { 0 } else { 1 };
+        if i == 0 {
+            // a=T transmits and displays, C=1 leaves the cursor alone and
+            // q=2 silences the terminal replies that would end up as key input
+            write!(
+                input,
+                "\x1b_Ga=T,f=100,c={},r={},i={},C=1,q=2,m={};",
+                columns, rows, IMAGE_ID, more
+            )?;
+        } else {
+            write!(input, "\x1b_Gm={};", more)?;
+        }
+        input.extend_from_slice(chunk);
+        input.extend_from_slice(b"\x1b\\");
+    }
+
+    Ok((input, crop))
+}
@@ -0,0 +1,341 @@
+mod external;
+mod halfblock;
+mod iterm;
+mod kitty;
+mod sixel;
+mod w3m;
+
+use anyhow::{anyhow, Result};
+use ratatui::{backend::Backend, buffer::Buffer, layout::Rect, terminal::Frame, widgets::Widget};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use termion::raw::IntoRawMode;
+
+use self::external::ExternalDisplay;
+use self::halfblock::HalfBlockDisplay;
+use self::iterm::Iterm;
+use self::kitty::Kitty;
+use self::sixel::Sixel;
+use self::w3m::ImageDisplay;
+use crate::graphics::{clear_area, pixel_size};
+use crate::prefetch::ImageLoader;
+use crate::view::{Crop, View};
+
+// How long the terminal gets to answer queries, they all answer the device
+// attributes one right away
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// What to draw and where
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Placement {
+    pub image_path: PathBuf,
+    /// Frame of animated images, 0 for still ones
+    pub frame: usize,
+    pub view: View,
+    /// Area of the image block
+    pub block: Rect,
+    /// Area of the whole terminal
+    pub terminal: Rect,
+}
+
+/// A way of drawing images in the terminal
+pub trait ImageRenderer {
+    /// Draw the image inside the block of the placement. Renderers made of
+    /// text write their cells to `buf`, the rest talk to the terminal
+    /// directly. Returns the part of the image that is visible, if known.
+    fn render_image(&mut self, placement: &Placement, buf: &mut Buffer) -> Result<Option<Crop>>;
+
+    /// Remove whatever image is on screen, e.g. when leaving the Main tab
+    fn clear(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the images around `current` ready in the background, so moving
+    /// to them is instant
+    fn prefetch(&mut self, images: &[PathBuf], current: usize, block: Rect, terminal: Rect) {
+        if let Some(loader) = self.loader() {
+            let (max_width, max_height) = pixel_size(block, terminal);
+            loader.prefetch(images, current, max_width, max_height);
+        }
+    }
+
+    /// Where the renderer gets its decoded images from, if it decodes them
+    fn loader(&self) -> Option<&ImageLoader> {
+        None
+    }
+}
+
+/// The escape codes of a terminal graphics protocol
+trait Protocol {
+    /// Codes drawing the image of the placement inside its block
+    fn input(&mut self, loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)>;
+
+    /// Remove the image drawn for the placement
+    fn erase(&mut self, placement: &Placement) -> Result<()> {
+        clear_area(placement.block)
+    }
+}
+
+/// Renders images by writing the escape codes of a protocol to the terminal
+struct ProtocolDisplay<P> {
+    protocol: P,
+    loader: ImageLoader,
+    // Image and areas of the last placement, to avoid re-transmitting the
+    // same image on every tick
+    last: Option<(Placement, Crop)>,
+}
+
+impl<P: Protocol> ProtocolDisplay<P> {
+    fn new(protocol: P, loader: ImageLoader) -> Self {
+        ProtocolDisplay {
+            protocol,
+            loader,
+            last: None,
+        }
+    }
+}
+
+impl<P: Protocol> ImageRenderer for ProtocolDisplay<P> {
+    fn render_image(&mut self, placement: &Placement, _buf: &mut Buffer) -> Result<Option<Crop>> {
+        if let Some((last, crop)) = &self.last {
+            if last == placement {
+                return Ok(Some(*crop));
+            }
+        }
+
+        self.clear()?;
+        let (input, crop) = self.protocol.input(&self.loader, placement)?;
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&input)?;
+        stdout.flush()?;
+
+        self.last = Some((placement.clone(), crop));
+        Ok(Some(crop))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        if let Some((placement, _)) = self.last.take() {
+            self.protocol.erase(&placement)?;
+        }
+        Ok(())
+    }
+
+    fn loader(&self) -> Option<&ImageLoader> {
+        Some(&self.loader)
+    }
+}
+
+/// Graphics the terminal answered it understands
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Capabilities {
+    pub kitty: bool,
+    pub sixel: bool,
+}
+
+impl Capabilities {
+    /// Ask the terminal with a kitty graphics query followed by primary
+    /// device attributes (DA1). Terminals ignore the queries they don't
+    /// know, but all of them answer DA1, which lists sixel support as `4`.
+    pub fn query() -> Capabilities {
+        Capabilities::ask().unwrap_or_default()
+    }
+
+    fn ask() -> Result<Capabilities> {
+        let tty = termion::get_tty()?;
+        let mut reader = tty.try_clone()?;
+        let mut tty = tty.into_raw_mode()?;
+        write!(tty, "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c")?;
+        tty.flush()?;
+
+        let reply = read_reply(&mut reader)?;
+        let reply = String::from_utf8_lossy(&reply);
+        let attributes = reply
+            .rfind("\x1b[?")
+            .map_or("", |start| &reply[start + 3..])
+            .trim_end_matches('c');
+        Ok(Capabilities {
+            kitty: reply.contains("\x1b_Gi=31;OK"),
+            sixel: attributes.split(';').any(|attribute| attribute == "4"),
+        })
+    }
+}
+
+// Read what the terminal answers up to the end of the DA1 reply, `ESC [ ?
+// attributes c`, the keys typed after it are left to the key reader
+fn read_reply(tty: &mut File) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut reply = vec![];
+    let mut byte = [0u8];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd {
+            fd: tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: a single pollfd, valid for the duration of the call
+        if unsafe { libc::poll(&mut fd, 1, left.as_millis() as libc::c_int) } <= 0 {
+            return Ok(reply);
+        }
+        if tty.read(&mut byte)? == 0 {
+            return Ok(reply);
+        }
+        reply.push(byte[0]);
+        let da1 = reply.windows(3).any(|window| window == b"\x1b[?");
+        if da1 && byte[0] == b'c' {
+            return Ok(reply);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RendererKind {
+    Auto,
+    W3m,
+    Kitty,
+    Sixel,
+    Iterm,
+    HalfBlock,
+    External,
+}
+
+// Candidates for `auto`, from best to worst
+const AUTO_ORDER: [RendererKind; 5] = [
+    RendererKind::Kitty,
+    RendererKind::Iterm,
+    RendererKind::Sixel,
+    RendererKind::W3m,
+    RendererKind::HalfBlock,
+];
+
+impl RendererKind {
+    pub const VARIANTS: &'static [&'static str] = &[
+        "auto",
+        "w3m",
+        "kitty",
+        "sixel",
+        "iterm",
+        "halfblock",
+        "external",
+    ];
+
+    /// Whether the renderer is expected to work in the running terminal,
+    /// given what it answered to queries
+    pub fn is_supported(self, terminal: Capabilities) -> bool {
+        match self {
+            RendererKind::Auto | RendererKind::HalfBlock => true,
+            RendererKind::W3m => ImageDisplay::is_supported(),
+            RendererKind::Kitty => Kitty::is_supported(terminal),
+            RendererKind::Sixel => Sixel::is_supported(terminal),
+            RendererKind::Iterm => Iterm::is_supported(),
+            // Only used when explicitly asked for
+            RendererKind::External => false,
+        }
+    }
+}
+
+impl FromStr for RendererKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(RendererKind::Auto),
+            "w3m" => Ok(RendererKind::W3m),
+            "kitty" => Ok(RendererKind::Kitty),
+            "sixel" => Ok(RendererKind::Sixel),
+            "iterm" => Ok(RendererKind::Iterm),
+            "halfblock" => Ok(RendererKind::HalfBlock),
+            "external" => Ok(RendererKind::External),
+            _ => Err(anyhow!("unknown renderer `{}`", s)),
+        }
+    }
+}
+
+/// Settings shared by all renderers
+pub struct RendererOptions {
+    pub command: Option<String>,
+    pub loader: ImageLoader,
+}
+
+/// Create the renderer of the given kind, `Auto` probes the terminal and
+/// picks the best one supported
+pub fn create_renderer(
+    kind: RendererKind,
+    options: RendererOptions,
+) -> Result<Box<dyn ImageRenderer>> {
+    let kind = match kind {
+        RendererKind::Auto => {
+            let terminal = Capabilities::query();
+            AUTO_ORDER
+                .iter()
+                .copied()
+                .find(|kind| kind.is_supported(terminal))
+                .unwrap_or(RendererKind::HalfBlock)
+        }
+        kind => kind,
+    };
+
+    Ok(match kind {
+        RendererKind::Auto | RendererKind::HalfBlock => {
+            Box::new(HalfBlockDisplay::new(options.loader))
+        }
+        RendererKind::W3m => Box::new(ImageDisplay::new(options.loader)?),
+        RendererKind::Kitty => Box::new(ProtocolDisplay::new(Kitty, options.loader)),
+        RendererKind::Sixel => Box::new(ProtocolDisplay::new(Sixel, options.loader)),
+        RendererKind::Iterm => Box::new(ProtocolDisplay::new(Iterm, options.loader)),
+        RendererKind::External => {
+            let command = options
+                .command
+                .ok_or_else(|| anyhow!("the external renderer requires --renderer-command"))?;
+            Box::new(ExternalDisplay::new(&command)?)
+        }
+    })
+}
+
+/// Draw an image in the frame with the given renderer
+pub fn render_image<B>(
+    f: &mut Frame<B>,
+    renderer: &mut dyn ImageRenderer,
+    image_path: PathBuf,
+    frame: usize,
+    view: View,
+    block: Rect,
+) -> Result<Option<Crop>>
+where
+    B: Backend,
+{
+    let mut result = Ok(None);
+    let widget = ImageWidget {
+        renderer,
+        placement: Placement {
+            image_path,
+            frame,
+            view,
+            block,
+            terminal: f.size(),
+        },
+        result: &mut result,
+    };
+    f.render_widget(widget, block);
+    result
+}
+
+// Frame doesn't hand out its buffer, widgets are the only way to get it
+struct ImageWidget<'a> {
+    renderer: &'a mut dyn ImageRenderer,
+    placement: Placement,
+    result: &'a mut Result<Option<Crop>>,
+}
+
+impl<'a> Widget for ImageWidget<'a> {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        *self.result = self.renderer.render_image(&self.placement, buf);
+    }
+}
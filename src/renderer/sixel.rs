@@ -0,0 +1,135 @@
+use anyhow::Result;
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use std::{env, io::Write};
+use termion::cursor::Goto;
+
+use super::{Capabilities, Placement, Protocol};
+use crate::graphics::pixel_size;
+use crate::prefetch::ImageLoader;
+use crate::view::Crop;
+
+const PALETTE_SIZE: usize = 256;
+// NeuQuant sampling factor, 1 is the slowest and best, 30 the fastest
+const QUANTIZER_SAMPLING: i32 = 10;
+
+/// Sixel graphics
+pub struct Sixel;
+
+impl Sixel {
+    /// Whether the running terminal understands sixel graphics
+    pub fn is_supported(terminal: Capabilities) -> bool {
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+        terminal.sixel
+            || term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || term.starts_with("contour")
+            || matches!(term_program.as_str(), "mlterm" | "contour")
+    }
+}
+
+impl Protocol for Sixel {
+    fn input(&mut self, loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+        sixel_input(loader, placement)
+    }
+}
+
+fn sixel_input(loader: &ImageLoader, placement: &Placement) -> Result<(Vec<u8>, Crop)> {
+    let Placement {
+        image_path,
+        frame,
+        view,
+        block,
+        terminal,
+    } = placement;
+    let (max_width, max_height) = pixel_size(*block, *terminal);
+
+    let (image, crop) = loader.load_view(image_path, *frame, *view, max_width, max_height)?;
+    let image = image.to_rgba8();
+
+    let mut input = format!("{}", Goto(block.x + 1, block.y + 1)).into_bytes();
+    encode(&image, &mut input)?;
+    Ok((input, crop))
+}
+
+/// Encode an image as a sixel sequence, with a palette of up to 256 colors
+fn encode(image: &RgbaImage, out: &mut Vec<u8>) -> Result<()> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let quantizer = NeuQuant::new(QUANTIZER_SAMPLING, PALETTE_SIZE, image.as_raw());
+    let indices: Vec<usize> = image
+        .pixels()
+        .map(|pixel| quantizer.index_of(&pixel.0))
+        .collect();
+
+    // P2=1 keeps the background of transparent pixels, raster attributes
+    // declare a 1:1 pixel aspect ratio and the image size
+    write!(out, "\x1bP0;1;0q\"1;1;{};{}", width, height)?;
+
+    // Sixel colors are expressed as RGB percentages
+    for (i, color) in quantizer.color_map_rgb().chunks(3).enumerate() {
+        write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            color[0] as u32 * 100 / 255,
+            color[1] as u32 * 100 / 255,
+            color[2] as u32 * 100 / 255
+        )?;
+    }
+
+    let mut band = vec![0u8; PALETTE_SIZE * width];
+    let mut used = vec![false; PALETTE_SIZE];
+
+    for band_y in (0..height).step_by(6) {
+        band.iter_mut().for_each(|b| *b = 0);
+        used.iter_mut().for_each(|u| *u = false);
+
+        // Every color gets a row of sixels, with one bit per pixel set
+        for y in band_y..(band_y + 6).min(height) {
+            let bit = 1 << (y - band_y);
+            for x in 0..width {
+                if image.get_pixel(x as u32, y as u32).0[3] == 0 {
+                    continue;
+                }
+                let color = indices[y * width + x];
+                band[color * width + x] |= bit;
+                used[color] = true;
+            }
+        }
+
+        let mut first = true;
+        for color in (0..PALETTE_SIZE).filter(|&c| used[c]) {
+            if !first {
+                // Graphics carriage return, draw the next color over the band
+                out.push(b'$');
+            }
+            first = false;
+            write!(out, "#{}", color)?;
+            encode_row(&band[color * width..(color + 1) * width], out)?;
+        }
+        // Graphics new line, move to the next band
+        out.push(b'-');
+    }
+
+    out.extend_from_slice(b"\x1b\\");
+    Ok(())
+}
+
+/// Write a row of sixels, run-length encoding repeated ones
+fn encode_row(row: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut i = 0;
+    while i < row.len() {
+        let sixel = row[i];
+        let run = row[i..].iter().take_while(|&&s| s == sixel).count();
+        let c = sixel + 63;
+        if run > 3 {
+            write!(out, "!{}{}", run, c as char)?;
+        } else {
+            out.extend(std::iter::repeat_n(c, run));
+        }
+        i += run;
+    }
+    Ok(())
+}
@@ -0,0 +1,345 @@
+use anyhow::{anyhow, Result};
+use ratatui::{buffer::Buffer, layout::Rect};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process,
+};
+use subprocess::{Popen, PopenConfig, Redirection};
+
+use super::{ImageRenderer, Placement};
+use crate::format::Format;
+use crate::image_info::{self, ImageInfo, Orientation};
+use crate::prefetch::ImageLoader;
+use crate::view::{Crop, Zoom};
+
+pub struct ImageDisplay {
+    path: String,
+    loader: ImageLoader,
+    coprocess: Option<Coprocess>,
+    // Font dimensions only change along with the terminal size
+    font: Option<(Rect, (u32, u32))>,
+    // Probed once per image, failures included
+    infos: HashMap<PathBuf, Result<ImageInfo, String>>,
+    // w3mimgdisplay ignores EXIF orientation and can't read some formats,
+    // those images are drawn from an upright temporary copy. Only the last
+    // one is kept, along with the original path, frame and size.
+    copy: Option<((PathBuf, usize, u32, u32), PathBuf)>,
+    copies: usize,
+    // Image and areas of the last draw, to avoid drawing the same image on
+    // every tick
+    last: Option<(Placement, Crop)>,
+}
+
+impl ImageDisplay {
+    pub fn new(loader: ImageLoader) -> Result<Self> {
+        if let Some(path) = ImageDisplay::find_path() {
+            Ok(ImageDisplay {
+                path,
+                loader,
+                coprocess: None,
+                font: None,
+                infos: HashMap::new(),
+                copy: None,
+                copies: 0,
+                last: None,
+            })
+        } else {
+            Err(anyhow!("w3mimgdisplay is not available!"))
+        }
+    }
+
+    /// w3mimgdisplay draws on the X window of the terminal, there is no
+    /// point in using it without one (e.g. over SSH)
+    pub fn is_supported() -> bool {
+        env::var_os("DISPLAY").is_some() && ImageDisplay::find_path().is_some()
+    }
+
+    fn find_path() -> Option<String> {
+        let mut paths = vec![
+            "/usr/lib/w3m/w3mimgdisplay",
+            "/usr/libexec/w3m/w3mimgdisplay",
+            "/usr/lib64/w3m/w3mimgdisplay",
+            "/usr/libexec64/w3m/w3mimgdisplay",
+            "/usr/local/libexec/w3m/w3mimgdisplay",
+        ];
+
+        let env_path = env::var("W3MIMGDISPLAY_PATH").unwrap_or_else(|_| "".to_string());
+        if !env_path.is_empty() {
+            paths.insert(0, &env_path);
+        }
+
+        let mut w3m_path: Option<&str> = None;
+
+        for path in paths {
+            if Path::new(path).exists() {
+                w3m_path = Some(path)
+            }
+        }
+
+        w3m_path.map(|path| path.to_string())
+    }
+
+    fn w3m_input(&mut self, placement: &Placement) -> Result<(String, Crop)> {
+        let Placement {
+            image_path,
+            frame,
+            view,
+            block,
+            terminal,
+        } = placement;
+        let (fontw, fonth) = self.font_dimensions(*terminal)?;
+
+        let start_x = (block.x as u32 + 1) * fontw;
+        let start_y = (block.y as u32 + 1) * fonth;
+
+        let (max_width, max_height) = self.max_size(*block, *terminal)?;
+
+        let info = self.image_info(image_path)?;
+        let (image_width, image_height) = info.display_dimensions();
+        let (crop, (width, height)) = view.layout(image_width, image_height, max_width, max_height);
+
+        // Crops are relative to the upright image, so the copy can only be
+        // downscaled when displaying it whole. w3mimgdisplay only draws the
+        // first frame of animations.
+        let native = info.orientation == Orientation::Normal
+            && *frame == 0
+            && Format::from_path(image_path).is_some_and(Format::w3m_native);
+        let source = match (native, view.zoom) {
+            (true, _) => image_path.to_path_buf(),
+            (false, Zoom::Fit) => self.decoded_copy(image_path, *frame, max_width, max_height)?,
+            (false, _) => self.decoded_copy(image_path, *frame, u32::MAX, u32::MAX)?,
+        };
+        let source_crop = match view.zoom {
+            Zoom::Fit => ";;;".to_string(),
+            Zoom::Percent(_) => format!("{};{};{};{}", crop.x, crop.y, crop.width, crop.height),
+        };
+
+        let input = format!(
+            "0;1;{};{};{};{};{};{}\n4;\n3;\n",
+            start_x,
+            start_y,
+            width,
+            height,
+            source_crop,
+            source.display()
+        );
+
+        Ok((input, crop))
+    }
+
+    /// Input to erase the image drawn in the given block
+    fn w3m_clear_input(&mut self, block: Rect, terminal: Rect) -> Result<String> {
+        let (fontw, fonth) = self.font_dimensions(terminal)?;
+
+        Ok(format!(
+            "6;{};{};{};{}\n4;\n3;\n",
+            block.x as u32 * fontw,
+            block.y as u32 * fonth,
+            (block.width as u32 + 1) * fontw,
+            (block.height as u32 + 1) * fonth,
+        ))
+    }
+
+    fn image_info(&mut self, image_path: &Path) -> Result<ImageInfo> {
+        let info = self
+            .infos
+            .entry(image_path.to_path_buf())
+            .or_insert_with(|| image_info::probe(image_path).map_err(|e| format!("{:#}", e)));
+        info.clone().map_err(|e| anyhow!("{}", e))
+    }
+
+    // Largest image that fits in the block, in pixels
+    fn max_size(&mut self, block: Rect, terminal: Rect) -> Result<(u32, u32)> {
+        let (fontw, fonth) = self.font_dimensions(terminal)?;
+        Ok((
+            (block.width as u32).saturating_sub(1) * fontw,
+            (block.height as u32).saturating_sub(1) * fonth,
+        ))
+    }
+
+    // A frame decoded by the loader, fitting inside (max_width, max_height),
+    // saved where w3mimgdisplay can read it. BMP is the quickest to write,
+    // animations are saved on every frame. Each copy gets a new name, as
+    // w3mimgdisplay doesn't read a file again while its name and size are
+    // the same.
+    fn decoded_copy(
+        &mut self,
+        image_path: &Path,
+        frame: usize,
+        max_width: u32,
+        max_height: u32,
+    ) -> Result<PathBuf> {
+        let key = (image_path.to_path_buf(), frame, max_width, max_height);
+        if let Some((copied, copy)) = &self.copy {
+            if *copied == key {
+                return Ok(copy.clone());
+            }
+        }
+
+        let image = self.loader.load(image_path, frame, max_width, max_height)?;
+        self.remove_copy();
+        let copy = env::temp_dir().join(format!(
+            "image-sorter-{}-{}.bmp",
+            process::id(),
+            self.copies
+        ));
+        self.copies += 1;
+        image.to_rgb8().save(&copy)?;
+        self.copy = Some((key, copy.clone()));
+        Ok(copy)
+    }
+
+    fn remove_copy(&mut self) {
+        if let Some((_, copy)) = self.copy.take() {
+            fs::remove_file(copy).ok();
+        }
+    }
+
+    /// The long lived w3mimgdisplay process, spawned on first use or again if
+    /// it died
+    fn coprocess(&mut self) -> Result<&mut Coprocess> {
+        let alive = match &mut self.coprocess {
+            Some(coprocess) => coprocess.process.poll().is_none(),
+            None => false,
+        };
+        if !alive {
+            self.coprocess = Some(Coprocess::spawn(&self.path)?);
+        }
+        Ok(self.coprocess.as_mut().unwrap())
+    }
+
+    fn font_dimensions(&mut self, terminal: Rect) -> Result<(u32, u32)> {
+        if let Some((size, dimensions)) = self.font {
+            if size == terminal {
+                return Ok(dimensions);
+            }
+        }
+
+        let path = self.path.clone();
+        let mut process = Popen::create(
+            &[path, "-test".to_string()],
+            PopenConfig {
+                stdout: Redirection::Pipe,
+                ..PopenConfig::default()
+            },
+        )?;
+
+        let (out, err) = process.communicate(None)?;
+        process.kill()?;
+        if err.is_some() || out.is_none() {
+            return Err(anyhow!("w3mimagedisplay failed -test"));
+        }
+
+        let outputs = out.unwrap();
+        let outputs = outputs.trim().split(' ').collect::<Vec<&str>>();
+
+        if outputs.len() < 2 {
+            return Err(anyhow!("w3mimagedisplay wrong output (font dimensions)"));
+        }
+
+        let xwidth = outputs[0].parse::<u32>()? + 2;
+        let xheight = outputs[1].parse::<u32>()? + 2;
+
+        let dimensions = (
+            xwidth / terminal.width as u32,
+            xheight / terminal.height as u32,
+        );
+        self.font = Some((terminal, dimensions));
+        Ok(dimensions)
+    }
+}
+
+impl ImageRenderer for ImageDisplay {
+    fn render_image(&mut self, placement: &Placement, _buf: &mut Buffer) -> Result<Option<Crop>> {
+        if let Some((last, crop)) = &self.last {
+            if last == placement {
+                return Ok(Some(*crop));
+            }
+        }
+
+        self.clear()?;
+        let (input, crop) = self.w3m_input(placement)?;
+        self.coprocess()?.communicate(&input)?;
+
+        self.last = Some((placement.clone(), crop));
+        Ok(Some(crop))
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        if let Some((placement, _)) = self.last.take() {
+            let input = self.w3m_clear_input(placement.block, placement.terminal)?;
+            self.coprocess()?.communicate(&input)?;
+        }
+        Ok(())
+    }
+
+    // Only the images w3mimgdisplay can't draw as is are decoded, at the
+    // size they are displayed whole
+    fn prefetch(&mut self, images: &[PathBuf], current: usize, block: Rect, terminal: Rect) {
+        if let Ok((max_width, max_height)) = self.max_size(block, terminal) {
+            let decoded = |path: &Path| !Format::from_path(path).is_some_and(Format::w3m_native);
+            self.loader
+                .prefetch_where(images, current, max_width, max_height, decoded);
+        }
+    }
+}
+
+impl Drop for ImageDisplay {
+    fn drop(&mut self) {
+        self.remove_copy();
+    }
+}
+
+/// w3mimgdisplay reading commands from a pipe, one per line
+struct Coprocess {
+    process: Popen,
+    stdin: File,
+    stdout: BufReader<File>,
+}
+
+impl Coprocess {
+    fn spawn(path: &str) -> Result<Self> {
+        let mut process = Popen::create(
+            &[path],
+            PopenConfig {
+                stdin: Redirection::Pipe,
+                stdout: Redirection::Pipe,
+                ..PopenConfig::default()
+            },
+        )?;
+        let stdin = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+
+        Ok(Coprocess {
+            process,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Send commands ending in `4;`, which answers with an empty line.
+    /// Waiting for it keeps us in sync with w3mimgdisplay.
+    fn communicate(&mut self, input: &str) -> Result<String> {
+        self.stdin.write_all(input.as_bytes())?;
+        self.stdin.flush()?;
+
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(anyhow!("w3mimagedisplay exited unexpectedly"));
+        }
+        Ok(line)
+    }
+}
+
+impl Drop for Coprocess {
+    fn drop(&mut self) {
+        // Dropping a running Popen waits for it, which would never happen
+        // while we hold its stdin
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
@@ -0,0 +1,175 @@
+use crossbeam_channel::Sender;
+use image::imageops::FilterType;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use crate::duplicates::map_parallel;
+use crate::event::Event;
+use crate::graphics;
+
+/// Images that look alike, e.g. burst shots or copies saved again, put next
+/// to each other
+#[derive(Default)]
+pub struct Similar {
+    /// Most bits two hashes can differ by for their images to look alike,
+    /// None when images aren't clustered
+    pub threshold: Option<u32>,
+    /// Whether images are being compared right now
+    pub clustering: bool,
+    clusters: Vec<Vec<PathBuf>>,
+    cluster_of: HashMap<PathBuf, usize>,
+}
+
+impl Similar {
+    pub fn new(threshold: Option<u32>) -> Self {
+        Similar {
+            threshold,
+            ..Similar::default()
+        }
+    }
+
+    /// Cluster images in the background, the clusters are sent as
+    /// `Event::Clusters`
+    pub fn start(&mut self, images: Vec<PathBuf>, events: Sender<Event>) {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        self.clustering = true;
+        thread::spawn(move || {
+            events
+                .send(Event::Clusters(clusters(images, threshold)))
+                .ok();
+        });
+    }
+
+    pub fn add_clusters(&mut self, clusters: Vec<Vec<PathBuf>>) {
+        self.clustering = false;
+        for cluster in clusters.into_iter().filter(|cluster| cluster.len() > 1) {
+            for image in cluster.iter() {
+                self.cluster_of.insert(image.clone(), self.clusters.len());
+            }
+            self.clusters.push(cluster);
+        }
+    }
+
+    /// The images that look like this one, itself included
+    pub fn cluster(&self, image: &Path) -> Option<&[PathBuf]> {
+        self.cluster_of
+            .get(image)
+            .map(|&cluster| self.clusters[cluster].as_slice())
+    }
+}
+
+/// Every image in a cluster, clusters in the order of their first image.
+/// Images that couldn't be decoded are on their own.
+pub fn clusters(images: Vec<PathBuf>, threshold: u32) -> Vec<Vec<PathBuf>> {
+    let hashes: HashMap<PathBuf, u64> = map_parallel(images.clone(), dhash).into_iter().collect();
+
+    // Union-find over the images, the ones close enough are joined through
+    // the tree
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+    let mut tree = BkTree::default();
+    for (i, image) in images.iter().enumerate() {
+        let hash = match hashes.get(image) {
+            Some(&hash) => hash,
+            None => continue,
+        };
+        for j in tree.find(hash, threshold) {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a.max(b)] = a.min(b);
+        }
+        tree.insert(hash, i);
+    }
+
+    let mut clusters: Vec<Vec<PathBuf>> = vec![];
+    let mut cluster_of: HashMap<usize, usize> = HashMap::new();
+    for (i, image) in images.into_iter().enumerate() {
+        let root = root(&mut parent, i);
+        let cluster = *cluster_of.entry(root).or_insert_with(|| {
+            clusters.push(vec![]);
+            clusters.len() - 1
+        });
+        clusters[cluster].push(image);
+    }
+    clusters
+}
+
+/// Difference hash: whether each pixel of a 9x8 grayscale thumbnail is
+/// brighter than the one on its right
+pub fn dhash(path: &Path) -> Option<u64> {
+    let (image, orientation) = graphics::decode(path).ok()?;
+    let thumbnail = orientation
+        .apply(image)
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    Some(hash)
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// Burkhard-Keller tree of hashes, to find the ones within a distance without
+// comparing them all
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<(u64, usize, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, value: usize) {
+        let new = self.nodes.len();
+        let mut node = 0;
+        while node < self.nodes.len() {
+            let distance = (self.nodes[node].0 ^ hash).count_ones();
+            match self.nodes[node].2.get(&distance) {
+                Some(&child) => node = child,
+                None => {
+                    self.nodes[node].2.insert(distance, new);
+                    break;
+                }
+            }
+        }
+        self.nodes.push((hash, value, HashMap::new()));
+    }
+
+    fn find(&self, hash: u64, threshold: u32) -> Vec<usize> {
+        let mut found = vec![];
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(node) = pending.pop() {
+            let (node_hash, value, children) = &self.nodes[node];
+            let distance = (node_hash ^ hash).count_ones();
+            if distance <= threshold {
+                found.push(*value);
+            }
+            let range = distance.saturating_sub(threshold)..=distance + threshold;
+            pending.extend(
+                children
+                    .iter()
+                    .filter(|(d, _)| range.contains(d))
+                    .map(|(_, child)| *child),
+            );
+        }
+        found
+    }
+}
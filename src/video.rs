@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageFormat};
+use std::{fs::File, path::Path, time::Duration};
+use subprocess::{Exec, NullFile, Redirection};
+
+use crate::bmff::{be_u32, be_u64, find_box, read_at};
+
+/// What is known about a video clip without decoding it
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Clip {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration: Option<Duration>,
+}
+
+impl Clip {
+    /// e.g. "1920x1080, 1:05"
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{}x{}", width, height));
+        }
+        if let Some(duration) = self.duration {
+            let seconds = duration.as_secs();
+            parts.push(format!("{}:{:02}", seconds / 60, seconds % 60));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Decode the first keyframe of a video with ffmpeg, if installed
+pub fn poster(path: &Path) -> Result<DynamicImage> {
+    let capture = Exec::cmd("ffmpeg")
+        .args(&["-v", "error", "-skip_frame", "nokey", "-i"])
+        .arg(path)
+        .args(&["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"])
+        .stdin(NullFile)
+        .stdout(Redirection::Pipe)
+        .stderr(NullFile)
+        .capture()
+        .map_err(|_| anyhow!("install ffmpeg to display a frame of video clips"))?;
+
+    if !capture.success() || capture.stdout.is_empty() {
+        return Err(anyhow!("ffmpeg could not decode {}", path.display()));
+    }
+    image::load_from_memory_with_format(&capture.stdout, ImageFormat::Png)
+        .map_err(|e| anyhow!("could not decode {}: {}", path.display(), e))
+}
+
+/// Read the resolution and duration of a clip. MP4 and QuickTime files are
+/// read natively, other containers need ffprobe.
+pub fn probe(path: &Path) -> Clip {
+    read_mp4(path).or_else(|| ffprobe(path)).unwrap_or_default()
+}
+
+fn ffprobe(path: &Path) -> Option<Clip> {
+    let capture = Exec::cmd("ffprobe")
+        .args(&["-v", "error", "-select_streams", "v:0"])
+        .args(&["-show_entries", "stream=width,height:format=duration"])
+        .args(&["-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .stdin(NullFile)
+        .stdout(Redirection::Pipe)
+        .stderr(NullFile)
+        .capture()
+        .ok()?;
+
+    let mut clip = Clip::default();
+    for line in capture.stdout_str().lines() {
+        match line.split_once('=') {
+            Some(("width", width)) => clip.width = width.parse().ok(),
+            Some(("height", height)) => clip.height = height.parse().ok(),
+            Some(("duration", duration)) => {
+                clip.duration = duration.parse().ok().map(Duration::from_secs_f64)
+            }
+            _ => {}
+        }
+    }
+    Some(clip)
+}
+
+// MP4 and QuickTime are made of nested boxes: the duration is in moov/mvhd,
+// and the display size of each track in moov/trak/tkhd
+fn read_mp4(path: &Path) -> Option<Clip> {
+    let mut file = File::open(path).ok()?;
+    let end = file.metadata().ok()?.len();
+    let (moov, moov_end) = find_box(&mut file, 0, end, b"moov")?;
+
+    let mut clip = Clip::default();
+    if let Some((mvhd, _)) = find_box(&mut file, moov, moov_end, b"mvhd") {
+        let header = read_at(&mut file, mvhd, 32)?;
+        // Version 1 uses 64 bit times
+        let (timescale, duration) = if header[0] == 1 {
+            (be_u32(&header[20..24]), be_u64(&header[24..32]))
+        } else {
+            (be_u32(&header[12..16]), be_u32(&header[16..20]) as u64)
+        };
+        // The duration is untrusted, a corrupt one only makes for a long clip
+        if timescale > 0 {
+            let millis = duration.saturating_mul(1000) / timescale as u64;
+            clip.duration = Some(Duration::from_millis(millis));
+        }
+    }
+
+    // The first track with a size is the video
+    let mut offset = moov;
+    while let Some((trak, trak_end)) = find_box(&mut file, offset, moov_end, b"trak") {
+        offset = trak_end;
+        let tkhd_end = match find_box(&mut file, trak, trak_end, b"tkhd") {
+            Some((tkhd, tkhd_end)) if tkhd_end.saturating_sub(tkhd) >= 8 => tkhd_end,
+            _ => continue,
+        };
+        // Width and height are the last fields, in 16.16 fixed point
+        let size = read_at(&mut file, tkhd_end - 8, 8)?;
+        let (width, height) = (be_u32(&size[..4]) >> 16, be_u32(&size[4..]) >> 16);
+        if width > 0 && height > 0 {
+            clip.width = Some(width);
+            clip.height = Some(height);
+            break;
+        }
+    }
+
+    Some(clip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn boxed(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        [&(content.len() as u32 + 8).to_be_bytes()[..], kind, content].concat()
+    }
+
+    fn mvhd(version: u8, timescale: u32, duration: u64) -> Vec<u8> {
+        let mut content = vec![version, 0, 0, 0];
+        if version == 1 {
+            content.extend_from_slice(&[0; 16]);
+            content.extend_from_slice(&timescale.to_be_bytes());
+            content.extend_from_slice(&duration.to_be_bytes());
+        } else {
+            content.extend_from_slice(&[0; 8]);
+            content.extend_from_slice(&timescale.to_be_bytes());
+            content.extend_from_slice(&(duration as u32).to_be_bytes());
+        }
+        content.extend_from_slice(&[0; 80]);
+        boxed(b"mvhd", &content)
+    }
+
+    fn tkhd(width: u32, height: u32) -> Vec<u8> {
+        let mut content = vec![0; 76];
+        content.extend_from_slice(&(width << 16).to_be_bytes());
+        content.extend_from_slice(&(height << 16).to_be_bytes());
+        boxed(b"trak", &boxed(b"tkhd", &content))
+    }
+
+    fn clip(name: &str, bytes: &[u8]) -> Option<Clip> {
+        let path = env::temp_dir().join(format!("image-sorter-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let clip = read_mp4(&path);
+        fs::remove_file(&path).unwrap();
+        clip
+    }
+
+    #[test]
+    fn mp4() {
+        // The audio track has no size
+        let moov = boxed(
+            b"moov",
+            &[mvhd(0, 1000, 65_500), tkhd(0, 0), tkhd(1920, 1080)].concat(),
+        );
+        let bytes = [boxed(b"ftyp", b"isom"), moov].concat();
+        assert_eq!(
+            clip("clip.mp4", &bytes),
+            Some(Clip {
+                width: Some(1920),
+                height: Some(1080),
+                duration: Some(Duration::from_millis(65_500)),
+            })
+        );
+
+        let moov = boxed(b"moov", &mvhd(1, 600, 600 * 3600));
+        let clip = clip("long.mov", &moov).unwrap();
+        assert_eq!(clip.duration, Some(Duration::from_secs(3600)));
+        assert_eq!(clip.width, None);
+    }
+
+    #[test]
+    fn corrupt_mp4() {
+        assert_eq!(clip("empty.mp4", b""), None);
+        assert_eq!(clip("no-moov.mp4", &boxed(b"ftyp", b"isom")), None);
+
+        // No timescale, and a huge duration
+        let moov = boxed(b"moov", &[mvhd(0, 0, 10), tkhd(640, 480)].concat());
+        assert_eq!(clip("timescale.mp4", &moov).unwrap().duration, None);
+        let moov = boxed(b"moov", &mvhd(1, 1, u64::MAX));
+        assert!(clip("duration.mp4", &moov).unwrap().duration.is_some());
+
+        // Cut in the middle of the track
+        let moov = boxed(b"moov", &[mvhd(0, 1000, 1000), tkhd(640, 480)].concat());
+        let clip = clip("cut.mp4", &moov[..moov.len() - 40]).unwrap();
+        assert_eq!(clip.duration, Some(Duration::from_secs(1)));
+    }
+}
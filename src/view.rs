@@ -0,0 +1,126 @@
+use crate::graphics::fit;
+
+// The center of the view is stored in ten thousandths of the image size, so
+// it doesn't depend on the image nor on the size of the pane
+const CENTER_SCALE: u32 = 10_000;
+// Panning moves the center this much at 100%, less when zoomed in further
+const PAN_STEP: u32 = 500;
+const MIN_ZOOM: u32 = 10;
+const MAX_ZOOM: u32 = 1600;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Zoom {
+    /// The whole image, scaled down to fit the pane
+    Fit,
+    /// Screen pixels per 100 image pixels
+    Percent(u32),
+}
+
+/// Which part of the image is displayed, and how big
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct View {
+    pub zoom: Zoom,
+    center: (u32, u32),
+}
+
+/// Rectangle of the image, in pixels
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View {
+            zoom: Zoom::Fit,
+            center: (CENTER_SCALE / 2, CENTER_SCALE / 2),
+        }
+    }
+}
+
+impl View {
+    pub fn zoom_in(&mut self) {
+        let percent = match self.zoom {
+            Zoom::Fit => 100,
+            Zoom::Percent(percent) => (percent * 5 / 4).clamp(MIN_ZOOM, MAX_ZOOM),
+        };
+        self.zoom = Zoom::Percent(percent);
+    }
+
+    pub fn zoom_out(&mut self) {
+        if let Zoom::Percent(percent) = self.zoom {
+            self.zoom = Zoom::Percent((percent * 4 / 5).clamp(MIN_ZOOM, MAX_ZOOM));
+        }
+    }
+
+    pub fn zoom_to(&mut self, zoom: Zoom) {
+        if zoom == Zoom::Fit {
+            *self = View::default();
+        } else {
+            self.zoom = zoom;
+        }
+    }
+
+    /// Move the view by the given number of steps in each axis
+    pub fn pan(&mut self, dx: i32, dy: i32) {
+        let percent = match self.zoom {
+            Zoom::Fit => return,
+            Zoom::Percent(percent) => percent,
+        };
+        let step = (PAN_STEP * 100 / percent).max(1) as i32;
+        let move_by = |c: u32, d: i32| (c as i32 + d * step).clamp(0, CENTER_SCALE as i32) as u32;
+        self.center = (move_by(self.center.0, dx), move_by(self.center.1, dy));
+    }
+
+    /// Part of an image of (width, height) that is visible in a pane of
+    /// (max_width, max_height) pixels, along with the size it is displayed at
+    pub fn layout(
+        &self,
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+    ) -> (Crop, (u32, u32)) {
+        let (width, height) = (width.max(1), height.max(1));
+        let percent = match self.zoom {
+            Zoom::Fit => {
+                let full = Crop {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                };
+                return (full, fit(width, height, max_width, max_height));
+            }
+            Zoom::Percent(percent) => percent as u64,
+        };
+
+        let crop_width = (max_width as u64 * 100 / percent).clamp(1, width as u64) as u32;
+        let crop_height = (max_height as u64 * 100 / percent).clamp(1, height as u64) as u32;
+
+        // Keep the crop inside the image, even if the center is near an edge
+        let center_x = (width as u64 * self.center.0 as u64 / CENTER_SCALE as u64) as u32;
+        let center_y = (height as u64 * self.center.1 as u64 / CENTER_SCALE as u64) as u32;
+        let x = center_x
+            .saturating_sub(crop_width / 2)
+            .min(width - crop_width);
+        let y = center_y
+            .saturating_sub(crop_height / 2)
+            .min(height - crop_height);
+
+        let crop = Crop {
+            x,
+            y,
+            width: crop_width,
+            height: crop_height,
+        };
+        let size = (
+            ((crop_width as u64 * percent / 100) as u32).clamp(1, max_width.max(1)),
+            ((crop_height as u64 * percent / 100) as u32).clamp(1, max_height.max(1)),
+        );
+        (crop, size)
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+use crate::discovery::Discovery;
+
+/// Watch folders for new images, sent as `Event::Discovered` once they are
+/// done being written, looked for the same way as `discovery` does. Images
+/// already there are left to discovery.
+#[cfg(target_os = "linux")]
+pub fn watch(folders: Vec<PathBuf>, discovery: &Discovery) -> Result<()> {
+    let events = match &discovery.events {
+        Some(events) => events.clone(),
+        None => return Ok(()),
+    };
+    let inotify =
+        inotify::Inotify::init().map_err(|e| anyhow!("could not watch for new images: {}", e))?;
+    let mut watcher = inotify_watcher::Watcher::new(
+        inotify,
+        discovery.recurse,
+        discovery.symlinks,
+        discovery.filter.clone(),
+    );
+    for folder in folders {
+        watcher.add(&folder, false);
+    }
+    let (formats, found, destinations) = (
+        discovery.formats.clone(),
+        discovery.found.clone(),
+        discovery.destinations.clone(),
+    );
+    std::thread::spawn(move || watcher.run(&formats, &found, &destinations, &events));
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_folders: Vec<PathBuf>, _discovery: &Discovery) -> Result<()> {
+    Err(anyhow!(
+        "watching for new images is only supported on Linux"
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod inotify_watcher {
+    use crossbeam_channel::Sender;
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+    use std::{
+        collections::{HashMap, HashSet},
+        ffi::OsString,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::discovery::{file_id, is_image, FileId, Found, Images, Symlinks};
+    use crate::duplicates::Destinations;
+    use crate::event::Event;
+    use crate::filter::Filter;
+    use crate::format::Formats;
+
+    // How long a new file has to keep the same size to be taken as complete,
+    // scanners and tethered cameras write them bit by bit
+    const SETTLE_TIME: Duration = Duration::from_secs(1);
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub struct Watcher {
+        inotify: Inotify,
+        folders: HashMap<WatchDescriptor, PathBuf>,
+        // A symbolic link may lead back to a folder already watched
+        watched: HashSet<FileId>,
+        recurse: bool,
+        symlinks: Symlinks,
+        filter: Arc<Filter>,
+        // New files, with their last known size and when it last changed
+        pending: HashMap<PathBuf, (u64, Instant)>,
+    }
+
+    impl Watcher {
+        pub fn new(
+            inotify: Inotify,
+            recurse: bool,
+            symlinks: Symlinks,
+            filter: Arc<Filter>,
+        ) -> Self {
+            Watcher {
+                inotify,
+                folders: HashMap::new(),
+                watched: HashSet::new(),
+                recurse,
+                symlinks,
+                filter,
+                pending: HashMap::new(),
+            }
+        }
+
+        /// Watch a folder, and its subfolders when recursing. Files in it
+        /// are taken as new if the folder itself is.
+        pub fn add(&mut self, folder: &Path, new: bool) {
+            if file_id(folder).is_some_and(|id| !self.watched.insert(id)) {
+                return;
+            }
+            let mask = WatchMask::CREATE
+                | WatchMask::MODIFY
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::MOVED_TO;
+            // Running out of watches only means missing some images
+            if let Ok(wd) = self.inotify.watches().add(folder, mask) {
+                self.folders.insert(wd, folder.to_path_buf());
+            }
+
+            let entries = match folder.read_dir() {
+                Ok(entries) => entries,
+                Err(_) => return,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_dir = path.is_dir();
+                if !self.filter.allows(&path, is_dir) || !self.symlinks.allows(&path, is_dir) {
+                    continue;
+                }
+                if is_dir && self.recurse {
+                    self.add(&path, new);
+                } else if !is_dir && new {
+                    self.pending.insert(path, (0, Instant::now()));
+                }
+            }
+        }
+
+        pub fn run(
+            mut self,
+            formats: &Formats,
+            found: &Found,
+            destinations: &Destinations,
+            events: &Sender<Event>,
+        ) {
+            let mut buffer = [0; 4096];
+            loop {
+                let changes: Vec<(WatchDescriptor, EventMask, OsString)> =
+                    match self.inotify.read_events(&mut buffer) {
+                        Ok(changes) => changes
+                            .filter_map(|e| Some((e.wd, e.mask, e.name?.to_os_string())))
+                            .collect(),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => vec![],
+                        Err(_) => return,
+                    };
+                for (wd, mask, name) in changes {
+                    if let Some(folder) = self.folders.get(&wd) {
+                        let path = folder.join(name);
+                        self.changed(path, mask);
+                    }
+                }
+
+                let mut images = Images::default();
+                for path in self.settled() {
+                    if is_image(&path, formats) && found.insert(&path) {
+                        images.push(path, destinations);
+                    }
+                }
+                if !images.paths.is_empty() && events.send(Event::Discovered(images)).is_err() {
+                    return;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        fn changed(&mut self, path: PathBuf, mask: EventMask) {
+            let is_dir = mask.contains(EventMask::ISDIR);
+            if !self.filter.allows(&path, is_dir) || !self.symlinks.allows(&path, is_dir) {
+                return;
+            }
+            if is_dir {
+                if self.recurse && mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                    self.add(&path, true);
+                }
+            } else {
+                self.pending
+                    .entry(path)
+                    .or_insert_with(|| (0, Instant::now()));
+            }
+        }
+
+        // Files which haven't grown in a while, the ones gone are forgotten
+        fn settled(&mut self) -> Vec<PathBuf> {
+            let now = Instant::now();
+            let mut settled = vec![];
+            self.pending.retain(|path, (size, changed)| {
+                let current = match path.metadata() {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => return false,
+                };
+                if current != *size {
+                    *size = current;
+                    *changed = now;
+                    true
+                } else if now - *changed >= SETTLE_TIME {
+                    settled.push(path.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+            settled
+        }
+    }
+}